@@ -8,12 +8,14 @@ then
     FRAMES=${FRAMES:-60}
     FPS=${FPS:-30}
     SAMPLES=${SAMPLES:-1024}
+    DENOISE=${DENOISE:-}
 else
     echo "Using fast settings. Set FINAL=1 for higher quality."
     SIZE=${SIZE:-256}
     FRAMES=${FRAMES:-30}
     FPS=${FPS:-15}
     SAMPLES=${SAMPLES:-48}
+    DENOISE=${DENOISE:-atrous}
 fi
 
 echo
@@ -31,7 +33,7 @@ mkdir demo
 
 echo
 echo "Rendering $FRAMES ${SIZE}x$SIZE frames with $SAMPLES samples per pixel."
-./target/release/sidequest -w $SIZE -h $SIZE -s $SAMPLES -f $FRAMES 'demo/frame%n.png'
+./target/release/sidequest -w $SIZE -h $SIZE -s $SAMPLES -f $FRAMES ${DENOISE:+--denoise $DENOISE} 'demo/frame%n.png'
 
 echo
 echo "Using ffmpeg to produce video."
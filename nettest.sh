@@ -0,0 +1,42 @@
+#! /bin/bash
+
+# Renders a small frame locally, then again with a coordinator and two local
+# workers, and checks that both come out the same.
+
+set -e
+
+PORT=${PORT:-7879}
+ARGS="-w 64 -h 64 -s 4 -f 1 --tilesize 16 --seed 7"
+OUT=$(mktemp -d)
+trap 'kill $(jobs -p) 2>/dev/null || true; rm -rf "$OUT"' EXIT
+
+# no preview window is needed
+export SDL_VIDEODRIVER=dummy
+
+cargo build --release
+BIN=./target/release/sidequest
+
+echo "Rendering locally."
+$BIN $ARGS "$OUT/local%n.png"
+
+echo "Rendering with two workers on 127.0.0.1:$PORT."
+$BIN $ARGS --threads 0 --listen "127.0.0.1:$PORT" "$OUT/remote%n.png" &
+COORDINATOR=$!
+sleep 1
+$BIN --threads 2 worker "127.0.0.1:$PORT" &
+FIRST=$!
+$BIN --threads 2 worker "127.0.0.1:$PORT" &
+SECOND=$!
+
+# every process should finish cleanly on its own
+wait $COORDINATOR
+wait $FIRST
+wait $SECOND
+
+if cmp -s "$OUT/local0.png" "$OUT/remote0.png"
+then
+    echo "Frames match."
+else
+    echo "Frames differ!"
+    exit 1
+fi
@@ -0,0 +1,108 @@
+//! Arbitrary output variables: extra per-pixel data rendered alongside the
+//! final image, for use in compositing.
+
+use std::str::FromStr;
+use palette::LinSrgb;
+use failure::{Error, format_err};
+
+/// a single render pass
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Aov {
+    /// distance from the camera to the first surface
+    Depth,
+    /// world-space normal of the first surface
+    Normal,
+    /// reflectance of the first surface
+    Albedo,
+    /// index of the first object, starting at 1 (0 is the sky)
+    ObjectId,
+    /// light which bounced exactly once before reaching the camera
+    Direct,
+    /// light which bounced more than once before reaching the camera
+    Indirect,
+    /// light emitted straight towards the camera
+    Emission,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 7] = [
+        Aov::Depth, Aov::Normal, Aov::Albedo, Aov::ObjectId,
+        Aov::Direct, Aov::Indirect, Aov::Emission,
+    ];
+
+    /// name of this pass, used for layer and file names
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Emission => "emission",
+        }
+    }
+
+    /// names of the channels in this pass
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal => &["X", "Y", "Z"],
+            Aov::ObjectId => &["ID"],
+            _ => &["R", "G", "B"],
+        }
+    }
+
+    /// value of each channel of this pass at a pixel
+    pub fn values(self, s: &AovSample) -> [f32; 3] {
+        let rgb = |c: LinSrgb| [c.red, c.green, c.blue];
+        match self {
+            Aov::Depth => [s.depth, 0., 0.],
+            Aov::Normal => s.normal,
+            Aov::Albedo => rgb(s.albedo),
+            Aov::ObjectId => [s.id as f32, 0., 0.],
+            Aov::Direct => rgb(s.direct),
+            Aov::Indirect => rgb(s.indirect),
+            Aov::Emission => rgb(s.emission),
+        }
+    }
+}
+
+impl FromStr for Aov {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Aov, Error> {
+        Aov::ALL.iter()
+            .cloned()
+            .find(|a| a.name() == s)
+            .ok_or_else(|| format_err!("unknown AOV \"{}\"", s))
+    }
+}
+
+/// a list of passes, parsed from strings like "depth,normal" or "all"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AovList(pub Vec<Aov>);
+
+impl FromStr for AovList {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<AovList, Error> {
+        if s.trim() == "all" { return Ok(AovList(Aov::ALL.to_vec())) }
+        s.split(',')
+            .map(|a| a.trim().parse())
+            .collect::<Result<_, _>>()
+            .map(AovList)
+    }
+}
+
+/// values of every pass at a single pixel
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AovSample {
+    pub depth: f32,
+    pub normal: [f32; 3],
+    pub albedo: LinSrgb,
+    pub id: u32,
+    pub direct: LinSrgb,
+    pub indirect: LinSrgb,
+    pub emission: LinSrgb,
+}
@@ -0,0 +1,143 @@
+//! Lens apertures, which decide the shape and size of out-of-focus blur.
+
+use std::f64::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+use nalg::{Point2, Vector2};
+use image;
+use failure::{Error, format_err};
+
+/// a grayscale image used as an aperture, for custom bokeh shapes
+///
+/// Brighter pixels let through more light, so they are picked more often.
+#[derive(Clone, Debug)]
+pub struct ApertureImage {
+    width: usize,
+    height: usize,
+    /// cumulative brightness of each row, normalized to end at 1
+    rows: Vec<f64>,
+    /// cumulative brightness along each row, normalized to end at 1
+    cols: Vec<f64>,
+}
+
+/// find which bin of a cumulative distribution `u` falls in, and where in that bin
+fn pick(cdf: &[f64], u: f64) -> (usize, f64) {
+    let i = match cdf.binary_search_by(|c| c.partial_cmp(&u).unwrap()) {
+        Ok(i) => i + 1,
+        Err(i) => i,
+    }.min(cdf.len() - 1);
+    let start = if i == 0 { 0. } else { cdf[i - 1] };
+    let width = cdf[i] - start;
+    (i, if width > 0. { (u - start) / width } else { 0.5 })
+}
+
+/// turn values into a cumulative distribution, returning the total
+fn accumulate(values: &mut [f64]) -> f64 {
+    let mut sum = 0.;
+    for v in values.iter_mut() {
+        sum += *v;
+        *v = sum;
+    }
+    if sum > 0. {
+        for v in values.iter_mut() { *v /= sum }
+    }
+    sum
+}
+
+impl ApertureImage {
+    pub fn open(path: impl AsRef<Path>) -> Result<ApertureImage, Error> {
+        let img = image::open(path)?.to_luma();
+        let (width, height) = (img.width() as usize, img.height() as usize);
+
+        let mut rows = vec![0.; height];
+        let mut cols = vec![0.; width * height];
+        for y in 0..height {
+            let row = &mut cols[y * width..(y + 1) * width];
+            for (x, c) in row.iter_mut().enumerate() {
+                *c = img.get_pixel(x as u32, y as u32).data[0] as f64;
+            }
+            rows[y] = accumulate(row);
+        }
+        if accumulate(&mut rows) == 0. {
+            return Err(format_err!("aperture image is completely black"))
+        }
+
+        Ok(ApertureImage { width, height, rows, cols })
+    }
+
+    /// pick a point in `[-1, 1]` along the longer side of the image
+    fn sample(&self, (u, v): (f64, f64)) -> Vector2<f64> {
+        let (y, fy) = pick(&self.rows, u);
+        let (x, fx) = pick(&self.cols[y * self.width..(y + 1) * self.width], v);
+        let size = self.width.max(self.height) as f64;
+        Vector2::new(
+            (2. * (x as f64 + fx) - self.width as f64) / size,
+            (2. * (y as f64 + fy) - self.height as f64) / size,
+        )
+    }
+}
+
+/// the outline of an aperture
+#[derive(Clone, Debug)]
+pub enum ApertureShape {
+    Circle,
+    /// a regular polygon formed by straight blades, rotated in radians
+    Polygon { blades: u32, rotation: f64 },
+    Image(Arc<ApertureImage>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Aperture {
+    /// radius of the opening, in world units
+    pub radius: f64,
+    pub shape: ApertureShape,
+    /// how far the lens barrel clips the aperture towards the edge of the
+    /// film, relative to the radius (0 for none)
+    ///
+    /// This darkens the corners and gives out-of-focus highlights there a
+    /// cat-eye shape.
+    pub cat_eye: f64,
+}
+
+impl Aperture {
+    /// a circular aperture of the given radius
+    pub fn circle(radius: f64) -> Aperture {
+        Aperture { radius, shape: ApertureShape::Circle, cat_eye: 0. }
+    }
+
+    /// an aperture for a lens with the given focal length (in millimeters)
+    /// and f-number, in a world measured in meters
+    pub fn from_fstop(focal_length: f64, fstop: f64, shape: ApertureShape) -> Aperture {
+        Aperture { radius: focal_length / 1000. / (2. * fstop), shape, cat_eye: 0. }
+    }
+
+    /// pick a point on the aperture, relative to its center, from a uniform sample
+    ///
+    /// `film` is where on the film the ray lands, and is used for clipping by
+    /// the lens barrel. No point is returned if it was clipped.
+    pub fn sample(&self, (u, v): (f64, f64), film: Point2<f64>) -> Option<Vector2<f64>> {
+        let unit = match self.shape {
+            ApertureShape::Circle => {
+                let (r, theta) = (u.sqrt(), v * 2. * PI);
+                Vector2::new(r * theta.sin(), r * theta.cos())
+            },
+            ApertureShape::Polygon { blades, rotation } => {
+                // pick one of the triangles making up the polygon, then a point on it
+                let n = blades.max(3) as f64;
+                let side = (u * n).floor().min(n - 1.);
+                let u = u * n - side;
+                let (a, b) = ((side / n) * 2. * PI + rotation, ((side + 1.) / n) * 2. * PI + rotation);
+                let s = v.sqrt();
+                let (wa, wb) = (s * (1. - u), s * u);
+                Vector2::new(wa * a.sin() + wb * b.sin(), wa * a.cos() + wb * b.cos())
+            },
+            ApertureShape::Image(ref image) => image.sample((u, v)),
+        };
+
+        // the barrel is another circle, moving towards the edge of the film
+        let barrel = film.coords * self.cat_eye;
+        if self.cat_eye > 0. && (unit - barrel).norm() > 1. { return None }
+
+        Some(unit * self.radius)
+    }
+}
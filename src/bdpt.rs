@@ -0,0 +1,282 @@
+//! Bidirectional path tracing, which follows light forwards from emitters as
+//! well as backwards from the camera, and joins the two paths up in every way
+//! it can.
+//!
+//! The same path can be made by joining different lengths of camera and light
+//! paths, so each way is weighted by the balance heuristic against the others.
+//! Light paths are never joined straight to the camera, since cameras can't
+//! say where on the film a point would land, so the first surface the camera
+//! sees is always found from the camera's side.
+//!
+//! Light paths only start from emitters that can be picked (see `lights`),
+//! while other emitters are still found by camera paths. Media and
+//! subsurface scattering are left to the path tracer: here, surfaces only
+//! reflect diffusely or specularly.
+
+use std::f64::consts::PI;
+use nalg::{Point3, Vector3, Unit};
+use palette::LinSrgb;
+use camera::{Ray, Impact};
+use sample::{World, reflect, cosine_weighted_hemi};
+use sampler::Sampler;
+use spectral::Channels;
+use integrator::{Integrator, Radiance};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Kind {
+    Camera,
+    /// the start of a light path, on an emitter
+    Light,
+    Surface,
+}
+
+/// a point along a camera or light path
+#[derive(Copy, Clone, Debug)]
+struct Vertex {
+    kind: Kind,
+    point: Point3<f64>,
+    /// outward normal of the surface, or the direction the camera looked in
+    norm: Unit<Vector3<f64>>,
+    /// object the vertex is on, if any
+    object: Option<usize>,
+    /// light carried along the path to here, over the density of the path
+    beta: LinSrgb,
+    /// the path carried on from here by specular reflection
+    delta: bool,
+    /// density of finding this vertex from the side of the path it is on,
+    /// per unit area
+    pdf_fwd: f64,
+    /// density of finding it from the other side, per unit area
+    pdf_rev: f64,
+}
+
+/// direction and squared distance from one point to another
+fn towards(from: Point3<f64>, to: Point3<f64>) -> (Unit<Vector3<f64>>, f64) {
+    let d = to - from;
+    let d2 = d.norm_squared();
+    (Unit::new_unchecked(d / d2.sqrt()), d2)
+}
+
+impl Vertex {
+    /// cosine between the surface and a direction, which doesn't apply at the camera
+    fn cos(&self, dir: &Vector3<f64>) -> f64 {
+        match self.kind {
+            Kind::Camera => 1.,
+            _ => self.norm.dot(dir).abs(),
+        }
+    }
+
+    fn reflectivity(&self, world: &World) -> f64 {
+        self.object.map_or(0., |n| world.objects[n].material.reflectivity as f64)
+    }
+
+    /// how much of the light arriving from `from` leaves towards `to`, leaving
+    /// out specular reflection, which only ever goes one way
+    ///
+    /// At the start of a light path, this is only whether it shines that way.
+    fn f(&self, world: &World, from: Option<&Vertex>, to: &Vertex) -> f64 {
+        let (wi, _) = towards(self.point, to.point);
+        match (self.kind, from) {
+            (Kind::Light, _) => if wi.dot(&self.norm) > 0. { 1. } else { 0. },
+            (Kind::Surface, Some(from)) => {
+                let (wo, _) = towards(self.point, from.point);
+                // light is only reflected, never passed through
+                if wo.dot(&self.norm) * wi.dot(&self.norm) > 0. {
+                    (1. - self.reflectivity(world)) / PI
+                } else { 0. }
+            },
+            _ => 0.,
+        }
+    }
+
+    /// density of the path carrying on from here to `to`, having arrived from
+    /// `from`, per unit area at `to`
+    fn pdf(&self, world: &World, from: Option<&Vertex>, to: &Vertex) -> f64 {
+        let (wi, d2) = towards(self.point, to.point);
+        let solid_angle = match (self.kind, from) {
+            (Kind::Light, _) => wi.dot(&self.norm).max(0.) / PI,
+            (Kind::Surface, Some(_)) => self.f(world, from, to) * wi.dot(&self.norm).abs(),
+            _ => 0.,
+        };
+        solid_angle * to.cos(&wi) / d2
+    }
+}
+
+/// extend a path along a ray by up to `max` more vertices, where `pdf` is
+/// the density of the ray's direction per unit solid angle
+///
+/// Returns the light carried by the path if it escaped to the sky.
+fn walk<S: Sampler>(world: &World, mut ray: Ray, beta: LinSrgb, mut pdf: f64, max: usize, sampler: &mut S, path: &mut Vec<Vertex>) -> Option<LinSrgb> {
+    for _ in 0..max {
+        let hit = match world.hit_surface(ray) {
+            Some(hit) => hit,
+            None => return Some(beta),
+        };
+        let (n, o) = hit.data;
+        let point = ray.origin + ray.dir.unwrap() * hit.t;
+        let wo = -ray.dir.unwrap();
+        let mut vertex = Vertex {
+            kind: Kind::Surface,
+            point,
+            norm: hit.norm,
+            object: Some(n),
+            beta,
+            delta: false,
+            pdf_fwd: pdf * hit.norm.dot(&wo).abs() / (hit.t * hit.t),
+            pdf_rev: 0.,
+        };
+
+        // the filter and probability of either kind of reflection cancel
+        // out, so only the densities need to be kept
+        let refl = o.material.reflectivity as f64;
+        let (dir, pdf_next, pdf_back) = if sampler.next() < refl {
+            vertex.delta = true;
+            (reflect(ray.dir, hit.norm), 0., 0.)
+        } else {
+            let facing = if hit.norm.dot(&wo) > 0. { hit.norm } else { -hit.norm };
+            let cwh = cosine_weighted_hemi(sampler.next_2d());
+            let dir = Unit::new_unchecked(Impact { t: 0., norm: facing, data: () }.surface() * cwh.unwrap());
+            (dir, (1. - refl) * dir.dot(&facing) / PI, (1. - refl) * wo.dot(&facing) / PI)
+        };
+
+        if let Some(prev) = path.last_mut() {
+            prev.pdf_rev = pdf_back * prev.cos(&wo) / (hit.t * hit.t);
+        }
+        path.push(vertex);
+        ray = Ray::new(point, dir).at_time(ray.time);
+        pdf = pdf_next;
+    }
+    None
+}
+
+/// weight for a path made of `s` light vertices and `t` camera vertices,
+/// against every other way the same path could have been made
+fn mis_weight(world: &World, light: &[Vertex], camera: &[Vertex], s: usize, t: usize) -> f64 {
+    let mut light = light[..s].to_vec();
+    let mut camera = camera[..t].to_vec();
+    let pt = t - 1;
+
+    // find the densities each end would have had as part of the other path
+    if s == 0 {
+        let origin = world.lights().pdf(&world.objects, camera[pt].object.unwrap());
+        if origin == 0. {
+            // light paths never start here, so this is the only way
+            return 1.
+        }
+        let as_light = Vertex { kind: Kind::Light, ..camera[pt] };
+        camera[pt - 1].pdf_rev = as_light.pdf(world, None, &camera[pt - 1]);
+        camera[pt].pdf_rev = origin;
+    } else {
+        let qs = s - 1;
+        let before_qs = if s > 1 { Some(light[qs - 1]) } else { None };
+        camera[pt].pdf_rev = light[qs].pdf(world, before_qs.as_ref(), &camera[pt]);
+        camera[pt - 1].pdf_rev = camera[pt].pdf(world, Some(&light[qs]), &camera[pt - 1]);
+        light[qs].pdf_rev = camera[pt].pdf(world, Some(&camera[pt - 1]), &light[qs]);
+        if s > 1 {
+            light[qs - 1].pdf_rev = light[qs].pdf(world, Some(&camera[pt]), &light[qs - 1]);
+        }
+        light[qs].delta = false;
+    }
+    camera[pt].delta = false;
+
+    // compare with moving the join along either path, where specular
+    // reflection has no density but can't be joined at
+    let remap = |p: f64| if p != 0. { p } else { 1. };
+    let mut sum = 0.;
+    let mut ratio = 1.;
+    for i in (2..t).rev() {
+        ratio *= remap(camera[i].pdf_rev) / remap(camera[i].pdf_fwd);
+        if !camera[i].delta && !camera[i - 1].delta { sum += ratio }
+    }
+    ratio = 1.;
+    for i in (0..s).rev() {
+        ratio *= remap(light[i].pdf_rev) / remap(light[i].pdf_fwd);
+        if !light[i].delta && !(i > 0 && light[i - 1].delta) { sum += ratio }
+    }
+    1. / (1. + sum)
+}
+
+/// light carried by joining the first `s` light vertices to the first `t`
+/// camera vertices, unweighted
+fn connect(world: &World, light: &[Vertex], camera: &[Vertex], s: usize, t: usize, time: f64, channels: Channels) -> LinSrgb {
+    let black = LinSrgb::new(0., 0., 0.);
+    let pt = &camera[t - 1];
+
+    if s == 0 {
+        // the camera path found an emitter by itself, which only shines outwards
+        let (wo, _) = towards(pt.point, camera[t - 2].point);
+        if wo.dot(&pt.norm) <= 0. { return black }
+        let emission = world.objects[pt.object.unwrap()].material.emission;
+        return pt.beta * channels.radiance(emission)
+    }
+
+    let qs = &light[s - 1];
+    let f = qs.f(world, if s > 1 { Some(&light[s - 2]) } else { None }, pt)
+        * pt.f(world, Some(&camera[t - 2]), qs);
+    if f == 0. || !world.visible(pt.point, qs.point, time) { return black }
+
+    let (dir, d2) = towards(qs.point, pt.point);
+    let g = qs.cos(&dir) * pt.cos(&dir) / d2;
+    qs.beta * pt.beta * (f * g) as f32
+}
+
+/// joins paths traced from both the camera and the emitters
+#[derive(Copy, Clone, Debug)]
+pub struct Bidirectional {
+    /// most times light may bounce
+    pub bounce_limit: usize,
+}
+
+impl Integrator for Bidirectional {
+    fn radiance<S: Sampler>(&self, world: &World, ray: Ray, sampler: &mut S, channels: Channels) -> Radiance {
+        let limit = self.bounce_limit;
+        let white = LinSrgb::new(1., 1., 1.);
+        let mut out = Radiance::default();
+
+        let mut camera = vec![Vertex {
+            kind: Kind::Camera,
+            point: ray.origin,
+            norm: ray.dir,
+            object: None,
+            beta: white,
+            delta: false,
+            pdf_fwd: 1.,
+            pdf_rev: 0.,
+        }];
+        if let Some(beta) = walk(world, ray, white, 1., limit + 1, sampler, &mut camera) {
+            // only camera paths can reach the sky
+            out.add(camera.len() - 1, beta * channels.radiance(world.ambient));
+        }
+
+        let mut light = Vec::new();
+        if let Some((start, dir, pdf)) = world.lights().sample(&world.objects, ray.time, sampler) {
+            let emission = channels.radiance(world.objects[start.object].material.emission);
+            let vertex = Vertex {
+                kind: Kind::Light,
+                point: start.point,
+                norm: start.norm,
+                object: Some(start.object),
+                beta: emission / start.pdf as f32,
+                delta: false,
+                pdf_fwd: start.pdf,
+                pdf_rev: 0.,
+            };
+            light.push(vertex);
+
+            // emission is cosine weighted, so only a factor of pi is left
+            let ray = Ray::new(start.point, dir).at_time(ray.time);
+            walk(world, ray, vertex.beta * PI as f32, pdf, limit, sampler, &mut light);
+        }
+
+        for t in 2..=camera.len() {
+            for s in 0..=light.len() {
+                // as long as a path with that many bounces from the camera
+                if s + t > limit + 2 { break }
+                let c = connect(world, &light, &camera, s, t, ray.time, channels);
+                if c.red == 0. && c.green == 0. && c.blue == 0. { continue }
+                out.add(s + t - 2, c * mis_weight(world, &light, &camera, s, t) as f32);
+            }
+        }
+        out
+    }
+}
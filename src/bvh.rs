@@ -0,0 +1,207 @@
+//! Bounding volume hierarchies, so that rays only test the few things they
+//! might actually hit.
+
+use nalg::{Isometry3, Point3};
+use camera::{Ray, Impact};
+
+/// an axis-aligned bounding box
+#[derive(Copy, Clone, Debug)]
+pub struct Aabb {
+    pub min: Point3<f64>,
+    pub max: Point3<f64>,
+}
+
+impl Aabb {
+    /// a box containing nothing, which grows to fit whatever it is joined with
+    pub fn empty() -> Aabb {
+        use std::f64::{INFINITY, NEG_INFINITY};
+
+        Aabb {
+            min: Point3::new(INFINITY, INFINITY, INFINITY),
+            max: Point3::new(NEG_INFINITY, NEG_INFINITY, NEG_INFINITY),
+        }
+    }
+
+    /// a box holding a ball
+    pub fn ball(center: Point3<f64>, radius: f64) -> Aabb {
+        let r = Point3::new(radius, radius, radius).coords;
+        Aabb { min: center - r, max: center + r }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    /// the part of this box which is also in another
+    pub fn intersection(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point3::new(
+                self.min.x.max(other.min.x),
+                self.min.y.max(other.min.y),
+                self.min.z.max(other.min.z),
+            ),
+            max: Point3::new(
+                self.max.x.min(other.max.x),
+                self.max.y.min(other.max.y),
+                self.max.z.min(other.max.z),
+            ),
+        }
+    }
+
+    /// a box bigger by `amount` on every side
+    pub fn grown(&self, amount: f64) -> Aabb {
+        let a = Point3::new(amount, amount, amount).coords;
+        Aabb { min: self.min - a, max: self.max + a }
+    }
+
+    /// a box holding this one after it has been moved
+    pub fn transformed(&self, iso: &Isometry3<f64>) -> Aabb {
+        if !self.is_finite() { return *self }
+        (0..8).map(|i| Point3::new(
+            if i & 1 == 0 { self.min.x } else { self.max.x },
+            if i & 2 == 0 { self.min.y } else { self.max.y },
+            if i & 4 == 0 { self.min.z } else { self.max.z },
+        )).fold(Aabb::empty(), |b, p| {
+            let p = iso * p;
+            b.union(&Aabb { min: p, max: p })
+        })
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.min.coords.iter().chain(self.max.coords.iter()).all(|v| v.is_finite())
+    }
+
+    pub fn center(&self) -> Point3<f64> {
+        Point3::from_coordinates((self.min.coords + self.max.coords) / 2.)
+    }
+
+    /// distance along a ray at which it enters this box, if it does before `max_t`
+    pub fn hit(&self, ray: &Ray, max_t: f64) -> Option<f64> {
+        self.span(ray, max_t).map(|(near, _)| near)
+    }
+
+    /// distances along a ray at which it enters and leaves this box, if it
+    /// enters before `max_t`
+    pub fn span(&self, ray: &Ray, max_t: f64) -> Option<(f64, f64)> {
+        let mut near = 0f64;
+        let mut far = max_t;
+        for axis in 0..3 {
+            let inv = 1. / ray.dir[axis];
+            let a = (self.min[axis] - ray.origin[axis]) * inv;
+            let b = (self.max[axis] - ray.origin[axis]) * inv;
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+        }
+        if near <= far { Some((near, far)) } else { None }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    /// items `start..end` of the hierarchy's order
+    Leaf { bounds: Aabb, start: usize, end: usize },
+    /// two children, with the first directly after this node
+    Inner { bounds: Aabb, second: usize },
+}
+
+impl Node {
+    fn bounds(&self) -> &Aabb {
+        match *self {
+            Node::Leaf { ref bounds, .. } | Node::Inner { ref bounds, .. } => bounds,
+        }
+    }
+}
+
+/// a hierarchy over a list of items, referred to by their index
+#[derive(Clone, Debug)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    order: Vec<usize>,
+}
+
+/// most items to keep in a single leaf
+const LEAF_SIZE: usize = 2;
+
+impl Bvh {
+    /// build a hierarchy over items with the given bounds
+    pub fn new(bounds: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh { nodes: Vec::new(), order: (0..bounds.len()).collect() };
+        if !bounds.is_empty() {
+            bvh.build(bounds, 0, bounds.len());
+        }
+        bvh
+    }
+
+    fn build(&mut self, bounds: &[Aabb], start: usize, end: usize) {
+        let all = self.order[start..end].iter()
+            .fold(Aabb::empty(), |b, &i| b.union(&bounds[i]));
+        if end - start <= LEAF_SIZE {
+            self.nodes.push(Node::Leaf { bounds: all, start, end });
+            return
+        }
+
+        // split along the axis where the items are most spread out
+        let centers = self.order[start..end].iter()
+            .fold(Aabb::empty(), |b, &i| {
+                let c = bounds[i].center();
+                b.union(&Aabb { min: c, max: c })
+            });
+        let size = centers.max - centers.min;
+        let axis = if size.x >= size.y && size.x >= size.z { 0 } else if size.y >= size.z { 1 } else { 2 };
+        self.order[start..end].sort_by(|&a, &b| {
+            bounds[a].center()[axis].partial_cmp(&bounds[b].center()[axis])
+                .unwrap_or(::std::cmp::Ordering::Equal)
+        });
+
+        let node = self.nodes.len();
+        self.nodes.push(Node::Inner { bounds: all, second: 0 });
+        let mid = (start + end) / 2;
+        self.build(bounds, start, mid);
+        let second = self.nodes.len();
+        self.build(bounds, mid, end);
+        self.nodes[node] = Node::Inner { bounds: all, second };
+    }
+
+    /// find the closest impact along a ray, calling `cast` for each item
+    /// whose bounds the ray passes through
+    pub fn cast<T, F>(&self, ray: Ray, mut cast: F) -> Option<Impact<T>>
+        where F: FnMut(usize) -> Option<Impact<T>>
+    {
+        use std::f64::INFINITY;
+
+        let mut closest: Option<Impact<T>> = None;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() { stack.push(0) }
+
+        while let Some(n) = stack.pop() {
+            let max_t = closest.as_ref().map_or(INFINITY, |c| c.t);
+            if self.nodes[n].bounds().hit(&ray, max_t).is_none() { continue }
+
+            match self.nodes[n] {
+                Node::Leaf { start, end, .. } => for &i in &self.order[start..end] {
+                    if let Some(hit) = cast(i) {
+                        if closest.as_ref().map_or(true, |c| hit.t < c.t) {
+                            closest = Some(hit);
+                        }
+                    }
+                },
+                Node::Inner { second, .. } => {
+                    stack.push(second);
+                    stack.push(n + 1);
+                },
+            }
+        }
+        closest
+    }
+}
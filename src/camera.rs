@@ -3,16 +3,27 @@ pub use imgref::ImgVec;
 pub use nalg::geometry::{Isometry3, Perspective3};
 use ncol::shape::Ball;
 use ncol::query::{RayCast, Ray as NcolRay};
+use std::str::FromStr;
+use failure::{Error, format_err};
+pub use aperture::{Aperture, ApertureShape, ApertureImage};
+pub use motion::Motion;
 
 #[derive(Copy, Clone, Debug)]
 pub struct Ray {
     pub origin: Point3<f64>,
     pub dir: Unit<Vector3<f64>>,
+    /// when the ray was cast, in frames relative to the frame being rendered
+    pub time: f64,
 }
 
 impl Ray {
     pub fn new(origin: Point3<f64>, dir: Unit<Vector3<f64>>) -> Ray {
-        Ray { origin, dir }
+        Ray { origin, dir, time: 0. }
+    }
+
+    /// the same ray, cast at a different time
+    pub fn at_time(self, time: f64) -> Ray {
+        Ray { time, ..self }
     }
 
     pub fn col(self) -> NcolRay<Point3<f64>> {
@@ -48,6 +59,12 @@ impl<T> Impact<T> {
 
 pub trait Castable {
     fn cast<T>(&self, ray: Ray, data: T) -> Option<Impact<T>>;
+
+    /// the closest hit further than `t_min` along a ray, so that a ray
+    /// leaving a surface doesn't hit where it started
+    fn cast_from<T>(&self, ray: Ray, t_min: f64, data: T) -> Option<Impact<T>> {
+        self.cast(ray, data).filter(|i| i.t > t_min)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -63,6 +80,10 @@ impl Sphere {
             ball: Ball::new(radius),
         }
     }
+
+    pub fn radius(&self) -> f64 {
+        self.ball.radius()
+    }
 }
 
 impl Castable for Sphere {
@@ -86,6 +107,78 @@ impl Castable for Sphere {
 
 pub trait Camera<I> {
     fn look(&self, from: I) -> Option<Ray>;
+
+    /// the other way around from `look`: where light from a point would
+    /// land on the film, if the camera can tell
+    fn project(&self, _point: Point3<f64>) -> Option<Seen> {
+        None
+    }
+}
+
+impl<I, C: Camera<I> + ?Sized> Camera<I> for Box<C> {
+    fn look(&self, from: I) -> Option<Ray> {
+        (**self).look(from)
+    }
+
+    fn project(&self, point: Point3<f64>) -> Option<Seen> {
+        (**self).project(point)
+    }
+}
+
+/// where light from a point reaches a camera
+#[derive(Copy, Clone, Debug)]
+pub struct Seen {
+    /// point on the film the light lands on
+    pub film: Point2<f64>,
+    /// where the light enters the camera
+    pub origin: Point3<f64>,
+    /// how strongly the film responds to light from the point, as the
+    /// camera's importance times the cosine at the camera over the squared
+    /// distance
+    pub importance: f64,
+}
+
+/// everything a scene camera needs to pick a ray
+#[derive(Copy, Clone, Debug)]
+pub struct CameraSample {
+    /// point on the film
+    pub film: Point2<f64>,
+    /// uniform sample in `[0, 1)` for where on the lens light passes through
+    pub lens: Vector2<f64>,
+    /// when the ray is cast, in frames relative to the frame being rendered
+    pub time: f64,
+}
+
+/// a camera with a position and orientation in the world
+pub trait Placed {
+    fn position(&self) -> &Isometry3<f64>;
+    fn position_mut(&mut self) -> &mut Isometry3<f64>;
+}
+
+/// which kind of projection a scene should use
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+    Equirectangular,
+    Fisheye(FisheyeMapping),
+    Cubemap,
+}
+
+impl FromStr for Projection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Projection, Error> {
+        match s {
+            "perspective" => Ok(Projection::Perspective),
+            "orthographic" | "ortho" => Ok(Projection::Orthographic),
+            "equirectangular" | "equirect" => Ok(Projection::Equirectangular),
+            "fisheye" | "equidistant" => Ok(Projection::Fisheye(FisheyeMapping::Equidistant)),
+            "equisolid" => Ok(Projection::Fisheye(FisheyeMapping::Equisolid)),
+            "cubemap" => Ok(Projection::Cubemap),
+            _ => Err(format_err!("unknown projection \"{}\"", s)),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -94,14 +187,87 @@ pub struct PerspectiveCamera {
     pub projection: Perspective3<f64>,
 }
 
+/// largest film coordinates along each axis, for a film with the given aspect ratio
+///
+/// Pixels are square, so the longer side spans `[-1, 1]`.
+pub fn film_extent(aspect: f64) -> Vector2<f64> {
+    if aspect >= 1. {
+        Vector2::new(1., 1. / aspect)
+    } else {
+        Vector2::new(aspect, 1.)
+    }
+}
+
+/// the axis along which a field of view is measured
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FovAxis {
+    Horizontal,
+    Vertical,
+    Diagonal,
+}
+
+impl FromStr for FovAxis {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<FovAxis, Error> {
+        match s {
+            "horizontal" | "h" => Ok(FovAxis::Horizontal),
+            "vertical" | "v" => Ok(FovAxis::Vertical),
+            "diagonal" | "d" => Ok(FovAxis::Diagonal),
+            _ => Err(format_err!("unknown field of view axis \"{}\"", s)),
+        }
+    }
+}
+
+/// how much of the scene a perspective camera sees
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FieldOfView {
+    /// an angle in radians, measured along an axis of the film
+    Angle(FovAxis, f64),
+    /// a physical sensor and lens, all in millimeters
+    ///
+    /// The sensor is fit to the film along whichever axis fills it, like a
+    /// real camera cropping its sensor to the output format.
+    Sensor { width: f64, height: f64, focal_length: f64 },
+}
+
+impl FieldOfView {
+    /// vertical angle of view, given the film aspect ratio
+    pub fn vertical(self, aspect: f64) -> f64 {
+        // half the size of the film, one unit in front of the camera
+        let half_height = match self {
+            FieldOfView::Angle(FovAxis::Vertical, a) => (a / 2.).tan(),
+            FieldOfView::Angle(FovAxis::Horizontal, a) => (a / 2.).tan() / aspect,
+            FieldOfView::Angle(FovAxis::Diagonal, a) => (a / 2.).tan() / (1. + aspect * aspect).sqrt(),
+            FieldOfView::Sensor { width, height, focal_length } => {
+                if aspect >= width / height {
+                    width / (2. * focal_length) / aspect
+                } else {
+                    height / (2. * focal_length)
+                }
+            },
+        };
+        2. * half_height.atan()
+    }
+}
+
 impl PerspectiveCamera {
     pub fn new(position: Isometry3<f64>, fov: f64, near: f64, far: f64) -> PerspectiveCamera {
-        PerspectiveCamera { position, projection: Perspective3::new(1., fov, near, far) }
+        PerspectiveCamera::with_fov(position, FieldOfView::Angle(FovAxis::Vertical, fov), 1., near, far)
+    }
+
+    /// create a camera for a film with the given aspect ratio (width / height)
+    pub fn with_fov(position: Isometry3<f64>, fov: FieldOfView, aspect: f64, near: f64, far: f64) -> PerspectiveCamera {
+        PerspectiveCamera { position, projection: Perspective3::new(aspect, fov.vertical(aspect), near, far) }
     }
 }
 
 impl Camera<Point2<f64>> for PerspectiveCamera {
     fn look(&self, from: Point2<f64>) -> Option<Ray> {
+        // projection expects both axes in [-1, 1]
+        let extent = film_extent(self.projection.aspect());
+        let from = Point2::new(from.coords.x / extent.x, from.coords.y / extent.y);
+
         let near = Point3::new(from.coords.x, from.coords.y, 0.);
         let near = self.projection.unproject_point(&near);
         let far = Point3::new(from.coords.x, from.coords.y, -1.);
@@ -111,28 +277,410 @@ impl Camera<Point2<f64>> for PerspectiveCamera {
         let dir = Unit::new_normalize(distant - origin);
         Some(Ray::new(origin, dir))
     }
+
+    fn project(&self, point: Point3<f64>) -> Option<Seen> {
+        // every ray passes through the camera's position, looking along z
+        // with the film mirrored
+        let local = self.position.inverse() * point;
+        if local.z <= 0. { return None }
+        let tan_y = (self.projection.fovy() / 2.).tan();
+        let tan_x = tan_y * self.projection.aspect();
+        let (x, y) = (-local.x / local.z / tan_x, -local.y / local.z / tan_y);
+        if x.abs() > 1. || y.abs() > 1. { return None }
+
+        // importance is spread evenly over the film, which is 2 tan_x by
+        // 2 tan_y one unit in front of the camera
+        let extent = film_extent(self.projection.aspect());
+        let d2 = local.coords.norm_squared();
+        let cos = local.z / d2.sqrt();
+        Some(Seen {
+            film: Point2::new(x * extent.x, y * extent.y),
+            origin: self.position * Point3::origin(),
+            importance: 1. / (4. * tan_x * tan_y * cos.powi(4)) * cos / d2,
+        })
+    }
+}
+
+impl Placed for PerspectiveCamera {
+    fn position(&self) -> &Isometry3<f64> { &self.position }
+    fn position_mut(&mut self) -> &mut Isometry3<f64> { &mut self.position }
+}
+
+/// a camera where all rays are parallel, so that size does not change with distance
+#[derive(Clone, Debug)]
+pub struct OrthographicCamera {
+    pub position: Isometry3<f64>,
+    /// half the width and height of the visible area, in world units
+    pub extent: Vector2<f64>,
+}
+
+impl OrthographicCamera {
+    /// create a camera seeing `extent` units above and below its center, on
+    /// a film with the given aspect ratio (width / height)
+    pub fn new(position: Isometry3<f64>, extent: f64, aspect: f64) -> OrthographicCamera {
+        OrthographicCamera { position, extent: Vector2::new(extent * aspect, extent) }
+    }
 }
 
-pub struct DefocusCamera {
-    pub base: PerspectiveCamera,
+impl Camera<Point2<f64>> for OrthographicCamera {
+    fn look(&self, from: Point2<f64>) -> Option<Ray> {
+        let film = film_extent(self.extent.x / self.extent.y);
+        let x = from.coords.x / film.x * self.extent.x;
+        let y = from.coords.y / film.y * self.extent.y;
+
+        // film is mirrored the same way as the perspective projection
+        let origin = Point3::new(-x, -y, 0.);
+        let origin = self.position * origin;
+        let dir = Unit::new_unchecked(self.position * Vector3::z());
+        Some(Ray::new(origin, dir))
+    }
+}
+
+impl Placed for OrthographicCamera {
+    fn position(&self) -> &Isometry3<f64> { &self.position }
+    fn position_mut(&mut self) -> &mut Isometry3<f64> { &mut self.position }
+}
+
+/// a thin lens in front of another camera, which blurs everything away from
+/// the focal plane
+pub struct DefocusCamera<C = PerspectiveCamera> {
+    pub base: C,
     pub focal_distance: f64,
+    pub aperture: Aperture,
 }
 
-impl DefocusCamera {
-    pub fn new(base: PerspectiveCamera, focal_distance: f64) -> DefocusCamera {
-        DefocusCamera { base, focal_distance }
+impl<C> DefocusCamera<C> {
+    pub fn new(base: C, focal_distance: f64, aperture: Aperture) -> DefocusCamera<C> {
+        DefocusCamera { base, focal_distance, aperture }
     }
 }
 
-impl Camera<(Point2<f64>, Vector2<f64>)> for DefocusCamera {
-    fn look(&self, (from, offset): (Point2<f64>, Vector2<f64>)) -> Option<Ray> {
+impl<C: Camera<Point2<f64>> + Placed> Camera<CameraSample> for DefocusCamera<C> {
+    fn look(&self, CameraSample { film: from, lens, .. }: CameraSample) -> Option<Ray> {
         let base = match self.base.look(from) {
             Some(b) => b,
             None => return None,
         };
-        let offset = self.base.position * Vector3::new(offset[0], offset[1], 0.);
-        let origin = base.origin + offset;
-        let dir = base.dir.unwrap() - offset / self.focal_distance;
-        Some(Ray::new(origin, Unit::new_normalize(dir)))
+        let offset = match self.aperture.sample((lens.x, lens.y), from) {
+            Some(o) => o,
+            None => return None,
+        };
+
+        // everything on the focal plane stays sharp, wherever on the lens
+        // light passes through
+        let forward = self.base.position() * Vector3::z();
+        let focus = base.origin + base.dir.unwrap() * (self.focal_distance / base.dir.dot(&forward));
+
+        let origin = base.origin + self.base.position() * Vector3::new(offset.x, offset.y, 0.);
+        Some(Ray::new(origin, Unit::new_normalize(focus - origin)))
+    }
+
+    /// only a lens which has closed down to a pinhole can say where light lands
+    fn project(&self, point: Point3<f64>) -> Option<Seen> {
+        if self.aperture.radius > 0. || self.aperture.cat_eye > 0. { return None }
+        self.base.project(point)
+    }
+}
+
+/// a pinhole camera taking lens offsets, which it ignores
+pub struct Pinhole<C>(pub C);
+
+impl<C: Camera<Point2<f64>>> Camera<CameraSample> for Pinhole<C> {
+    fn look(&self, sample: CameraSample) -> Option<Ray> {
+        self.0.look(sample.film)
+    }
+
+    fn project(&self, point: Point3<f64>) -> Option<Seen> {
+        self.0.project(point)
+    }
+}
+
+/// a camera which moves while the shutter is open
+pub struct MovingCamera<C> {
+    pub base: C,
+    /// movement relative to where the base camera is placed
+    pub motion: Motion,
+}
+
+impl<C: Camera<CameraSample>> Camera<CameraSample> for MovingCamera<C> {
+    fn look(&self, sample: CameraSample) -> Option<Ray> {
+        let ray = self.base.look(sample)?;
+        Some(self.motion.apply(ray.at_time(sample.time)))
+    }
+}
+
+/// direction in camera space, looking along +z, with film axes mirrored
+/// the same way as the perspective projection
+fn camera_dir(position: &Isometry3<f64>, theta: f64, phi: f64) -> Ray {
+    let dir = Vector3::new(
+        -theta.sin() * phi.cos(),
+        -theta.sin() * phi.sin(),
+        theta.cos(),
+    );
+    Ray::new(position * Point3::origin(), Unit::new_normalize(position * dir))
+}
+
+/// a full 360° by 180° view, with longitude along x and latitude along y
+///
+/// Pixels are square, so only a 2:1 film covers the full sphere.
+///
+/// A non-zero `eye_offset` gives omni-directional stereo: every ray starts
+/// on a circle of that radius, offset to the left (or to the right when
+/// negative) of its own viewing direction.
+#[derive(Clone, Debug)]
+pub struct EquirectangularCamera {
+    pub position: Isometry3<f64>,
+    pub eye_offset: f64,
+}
+
+impl Camera<Point2<f64>> for EquirectangularCamera {
+    fn look(&self, from: Point2<f64>) -> Option<Ray> {
+        use std::f64::consts::{PI, FRAC_PI_2};
+
+        let lon = from.coords.x * PI;
+        let lat = from.coords.y * PI;
+        if lat.abs() > FRAC_PI_2 { return None }
+
+        let dir = Vector3::new(-lon.sin() * lat.cos(), -lat.sin(), lon.cos() * lat.cos());
+        let origin = Point3::new(lon.cos(), 0., lon.sin()) * self.eye_offset;
+        Some(Ray::new(self.position * origin, Unit::new_normalize(self.position * dir)))
+    }
+}
+
+/// how a fisheye lens maps angles from the view axis to distances on the film
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FisheyeMapping {
+    /// distance is proportional to angle
+    Equidistant,
+    /// distance is proportional to sin(angle / 2), preserving area
+    Equisolid,
+}
+
+/// a fisheye lens, with an image circle of radius 1 on the film
+#[derive(Clone, Debug)]
+pub struct FisheyeCamera {
+    pub position: Isometry3<f64>,
+    /// angle across the full image circle
+    pub fov: f64,
+    pub mapping: FisheyeMapping,
+}
+
+impl Camera<Point2<f64>> for FisheyeCamera {
+    fn look(&self, from: Point2<f64>) -> Option<Ray> {
+        let r = from.coords.norm();
+        if r > 1. { return None }
+
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.fov / 2.,
+            FisheyeMapping::Equisolid => 2. * (r * (self.fov / 4.).sin()).asin(),
+        };
+        let phi = from.coords.y.atan2(from.coords.x);
+        Some(camera_dir(&self.position, theta, phi))
+    }
+}
+
+/// six 90° views laid out on a 3:2 film
+///
+/// The top row holds the left, front and right faces. The bottom row holds
+/// the back, up and down faces.
+#[derive(Clone, Debug)]
+pub struct CubemapCamera {
+    pub position: Isometry3<f64>,
+}
+
+impl Camera<Point2<f64>> for CubemapCamera {
+    fn look(&self, from: Point2<f64>) -> Option<Ray> {
+        // forward, right and down directions of each face in camera space
+        const FACES: [[[f64; 3]; 3]; 6] = [
+            [[1., 0., 0.], [0., 0., 1.], [0., -1., 0.]], // left
+            [[0., 0., 1.], [-1., 0., 0.], [0., -1., 0.]], // front
+            [[-1., 0., 0.], [0., 0., -1.], [0., -1., 0.]], // right
+            [[0., 0., -1.], [1., 0., 0.], [0., -1., 0.]], // back
+            [[0., 1., 0.], [-1., 0., 0.], [0., 0., 1.]], // up
+            [[0., -1., 0.], [-1., 0., 0.], [0., 0., -1.]], // down
+        ];
+
+        // film spans [-1, 1] across and [-2/3, 2/3] down
+        let u = (from.coords.x + 1.) * 1.5;
+        let v = from.coords.y * 1.5 + 1.;
+        if u < 0. || v < 0. || u >= 3. || v >= 2. { return None }
+        let (col, row) = (u.floor(), v.floor());
+        let [f, r, d] = FACES[row as usize * 3 + col as usize];
+
+        // position within the face, in [-1, 1]
+        let (fu, fv) = ((u - col) * 2. - 1., (v - row) * 2. - 1.);
+        let dir = Vector3::new(
+            f[0] + fu * r[0] + fv * d[0],
+            f[1] + fu * r[1] + fv * d[1],
+            f[2] + fu * r[2] + fv * d[2],
+        );
+        Some(Ray::new(self.position * Point3::origin(), Unit::new_normalize(self.position * dir)))
+    }
+}
+
+impl Placed for EquirectangularCamera {
+    fn position(&self) -> &Isometry3<f64> { &self.position }
+    fn position_mut(&mut self) -> &mut Isometry3<f64> { &mut self.position }
+}
+
+impl Placed for FisheyeCamera {
+    fn position(&self) -> &Isometry3<f64> { &self.position }
+    fn position_mut(&mut self) -> &mut Isometry3<f64> { &mut self.position }
+}
+
+impl Placed for CubemapCamera {
+    fn position(&self) -> &Isometry3<f64> { &self.position }
+    fn position_mut(&mut self) -> &mut Isometry3<f64> { &mut self.position }
+}
+
+/// how the eyes of a stereo rig are aimed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StereoMode {
+    /// parallel eyes, with film shifted so the views meet at the convergence distance
+    Parallel,
+    /// eyes rotated inwards to look at the convergence point
+    ToeIn,
+}
+
+impl FromStr for StereoMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<StereoMode, Error> {
+        match s {
+            "parallel" | "off-axis" => Ok(StereoMode::Parallel),
+            "toe-in" | "toein" => Ok(StereoMode::ToeIn),
+            _ => Err(format_err!("unknown stereo mode \"{}\"", s)),
+        }
+    }
+}
+
+/// where each eye ends up in the output
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StereoLayout {
+    /// left eye on the left half, right eye on the right half
+    SideBySide,
+    /// left eye on the top half, right eye on the bottom half
+    TopBottom,
+    /// rendered side by side, but saved to different files
+    Separate,
+}
+
+impl FromStr for StereoLayout {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<StereoLayout, Error> {
+        match s {
+            "sbs" | "side-by-side" => Ok(StereoLayout::SideBySide),
+            "tb" | "top-bottom" => Ok(StereoLayout::TopBottom),
+            "separate" => Ok(StereoLayout::Separate),
+            _ => Err(format_err!("unknown stereo layout \"{}\"", s)),
+        }
+    }
+}
+
+impl StereoLayout {
+    /// number of eye images across and down the full frame
+    pub fn grid(self) -> (usize, usize) {
+        match self {
+            StereoLayout::SideBySide | StereoLayout::Separate => (2, 1),
+            StereoLayout::TopBottom => (1, 2),
+        }
+    }
+}
+
+/// one eye of a stereo rig, moved sideways from a base camera
+#[derive(Clone, Debug)]
+pub struct EyeCamera<C> {
+    pub base: C,
+    /// sideways distance from the base camera, positive to the left
+    pub offset: f64,
+    /// distance at which parallel eyes converge, if their film is shifted
+    pub convergence: Option<f64>,
+}
+
+impl<C: Placed + Clone> EyeCamera<C> {
+    pub fn new(base: &C, mode: StereoMode, offset: f64, convergence: f64) -> EyeCamera<C> {
+        use nalg::{Translation3, UnitQuaternion};
+
+        let mut base = base.clone();
+        let shift = Translation3::new(offset, 0., 0.);
+        match mode {
+            StereoMode::Parallel => {
+                *base.position_mut() = *base.position() * shift;
+                EyeCamera { base, offset, convergence: Some(convergence) }
+            },
+            StereoMode::ToeIn => {
+                let turn = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), -offset.atan2(convergence));
+                *base.position_mut() = *base.position() * shift * turn;
+                EyeCamera { base, offset, convergence: None }
+            },
+        }
+    }
+}
+
+impl<C: Camera<Point2<f64>>> Camera<Point2<f64>> for EyeCamera<C> {
+    fn look(&self, from: Point2<f64>) -> Option<Ray> {
+        let ray = self.base.look(from)?;
+        match self.convergence {
+            Some(convergence) => {
+                // shear rays back towards the center on the film plane, like
+                // a shifted lens would, so that they cross the base camera's
+                // at the convergence distance
+                let position = self.base.position();
+                let local = position.inverse() * ray.dir.unwrap();
+                // rays which don't face forwards never reach the film plane
+                if local.z <= 0. { return Some(ray) }
+                let sheared = local / local.z - Vector3::new(self.offset / convergence, 0., 0.);
+                Some(Ray::new(ray.origin, Unit::new_normalize(position * sheared)))
+            },
+            None => Some(ray),
+        }
+    }
+
+    /// only eyes whose rays aren't bent can say where light lands
+    fn project(&self, point: Point3<f64>) -> Option<Seen> {
+        match self.convergence {
+            Some(_) if self.offset != 0. => None,
+            _ => self.base.project(point),
+        }
+    }
+}
+
+impl<C: Placed> Placed for EyeCamera<C> {
+    fn position(&self) -> &Isometry3<f64> { self.base.position() }
+    fn position_mut(&mut self) -> &mut Isometry3<f64> { self.base.position_mut() }
+}
+
+/// two eyes sharing one frame
+pub struct StereoRig<C> {
+    pub left: C,
+    pub right: C,
+    pub layout: StereoLayout,
+    /// size of a single eye's image, in pixels
+    pub eye_size: (f64, f64),
+}
+
+impl<C: Camera<CameraSample>> Camera<CameraSample> for StereoRig<C> {
+    fn look(&self, sample: CameraSample) -> Option<Ray> {
+        let from = sample.film;
+        let (w, h) = self.eye_size;
+        let (across, down) = self.layout.grid();
+
+        // convert from film of the full frame to film of a single eye
+        let full = (w * across as f64).max(h * down as f64);
+        let eye = w.max(h);
+        let (x, y) = (from.coords.x * full, from.coords.y * full);
+        let (left, x, y) = match self.layout {
+            StereoLayout::TopBottom if y < 0. => (true, x, y + h),
+            StereoLayout::TopBottom => (false, x, y - h),
+            _ if x < 0. => (true, x + w, y),
+            _ => (false, x - w, y),
+        };
+        let sample = CameraSample { film: Point2::new(x / eye, y / eye), ..sample };
+
+        match left {
+            true => self.left.look(sample),
+            false => self.right.look(sample),
+        }
     }
 }
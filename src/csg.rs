@@ -0,0 +1,266 @@
+//! Constructive solid geometry: exact boolean combinations of analytic
+//! solids, found by combining every span a ray spends inside each of them.
+//!
+//! Solids can be written as expressions, like
+//! `subtract(box(1, 1, 1), cylinder(0.5, 2))`.
+
+use std::str::FromStr;
+use nalg::{Isometry3, Point3, Vector3, Unit};
+use failure::{Error, format_err};
+use camera::{Ray, Castable, Impact};
+use shape::transform_ray;
+use bvh::Aabb;
+use expr::Call;
+
+/// where a ray crosses the surface of a solid
+#[derive(Copy, Clone, Debug)]
+pub struct Boundary {
+    pub t: f64,
+    /// outward normal of the solid
+    pub norm: Vector3<f64>,
+}
+
+impl Boundary {
+    fn flipped(self) -> Boundary {
+        Boundary { t: self.t, norm: -self.norm }
+    }
+}
+
+/// a stretch of a ray which is inside a solid
+#[derive(Copy, Clone, Debug)]
+pub struct Span {
+    pub enter: Boundary,
+    pub exit: Boundary,
+}
+
+/// spans of a ray inside a solid, in order and never overlapping
+pub type Spans = Vec<Span>;
+
+fn union(a: Spans, b: Spans) -> Spans {
+    let mut all: Spans = a.into_iter().chain(b).collect();
+    all.sort_by(|x, y| x.enter.t.partial_cmp(&y.enter.t).unwrap_or(::std::cmp::Ordering::Equal));
+
+    let mut out: Spans = Vec::with_capacity(all.len());
+    for span in all {
+        match out.last_mut() {
+            Some(last) if span.enter.t <= last.exit.t => {
+                if span.exit.t > last.exit.t { last.exit = span.exit }
+            },
+            _ => out.push(span),
+        }
+    }
+    out
+}
+
+fn intersection(a: Spans, b: Spans) -> Spans {
+    let mut out = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        let enter = if a[i].enter.t > b[j].enter.t { a[i].enter } else { b[j].enter };
+        let exit = if a[i].exit.t < b[j].exit.t { a[i].exit } else { b[j].exit };
+        if enter.t < exit.t { out.push(Span { enter, exit }) }
+
+        // whichever ends first can't overlap anything else
+        if a[i].exit.t < b[j].exit.t { i += 1 } else { j += 1 }
+    }
+    out
+}
+
+fn difference(a: Spans, b: Spans) -> Spans {
+    let mut out = Vec::new();
+    for span in a {
+        let mut rest = Some(span);
+        for cut in &b {
+            let cur = match rest {
+                Some(cur) => cur,
+                None => break,
+            };
+            if cut.exit.t <= cur.enter.t || cut.enter.t >= cur.exit.t { continue }
+
+            // surfaces of the cut face the other way once they are part of the result
+            if cut.enter.t > cur.enter.t {
+                out.push(Span { enter: cur.enter, exit: cut.enter.flipped() });
+            }
+            rest = if cut.exit.t < cur.exit.t {
+                Some(Span { enter: cut.exit.flipped(), exit: cur.exit })
+            } else {
+                None
+            };
+        }
+        out.extend(rest);
+    }
+    out
+}
+
+/// roots of `a t^2 + 2 b t + c`, in order
+fn quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    let disc = b * b - a * c;
+    if disc < 0. || a == 0. { return None }
+    let root = disc.sqrt();
+    Some(((-b - root) / a, (-b + root) / a))
+}
+
+/// a solid which a ray can be inside of
+#[derive(Clone, Debug, PartialEq)]
+pub enum Solid {
+    Sphere { radius: f64 },
+    /// a box with half its size along each axis
+    Cuboid { half: Vector3<f64> },
+    /// a capped cylinder around the y axis
+    Cylinder { radius: f64, half_height: f64 },
+    Union(Box<Solid>, Box<Solid>),
+    Intersection(Box<Solid>, Box<Solid>),
+    /// the first solid, with the second cut out of it
+    Difference(Box<Solid>, Box<Solid>),
+    Transform(Isometry3<f64>, Box<Solid>),
+}
+
+impl Solid {
+    /// every span of a ray which is inside this solid, including any behind
+    /// the start of the ray
+    pub fn spans(&self, ray: &Ray) -> Spans {
+        let o = ray.origin.coords;
+        let d = ray.dir.unwrap();
+        let at = |t: f64| o + d * t;
+
+        match *self {
+            Solid::Sphere { radius } => {
+                match quadratic(d.dot(&d), o.dot(&d), o.dot(&o) - radius * radius) {
+                    Some((t0, t1)) => vec![Span {
+                        enter: Boundary { t: t0, norm: at(t0) / radius },
+                        exit: Boundary { t: t1, norm: at(t1) / radius },
+                    }],
+                    None => vec![],
+                }
+            },
+            Solid::Cuboid { half } => {
+                use std::f64::INFINITY;
+
+                let mut enter = Boundary { t: -INFINITY, norm: Vector3::zeros() };
+                let mut exit = Boundary { t: INFINITY, norm: Vector3::zeros() };
+                for axis in 0..3 {
+                    let mut norm = Vector3::zeros();
+                    norm[axis] = 1.;
+                    if d[axis] == 0. {
+                        // parallel to these faces, so always or never between them
+                        if o[axis].abs() > half[axis] { return vec![] }
+                        continue
+                    }
+                    let a = (-half[axis] - o[axis]) / d[axis];
+                    let b = (half[axis] - o[axis]) / d[axis];
+                    let (near, far) = if a < b { (Boundary { t: a, norm: -norm }, Boundary { t: b, norm }) }
+                        else { (Boundary { t: b, norm }, Boundary { t: a, norm: -norm }) };
+                    if near.t > enter.t { enter = near }
+                    if far.t < exit.t { exit = far }
+                }
+                if enter.t < exit.t { vec![Span { enter, exit }] } else { vec![] }
+            },
+            Solid::Cylinder { radius, half_height } => {
+                let flat = |v: Vector3<f64>| Vector3::new(v.x, 0., v.z);
+                let (fo, fd) = (flat(o), flat(d));
+                let side = match quadratic(fd.dot(&fd), fo.dot(&fd), fo.dot(&fo) - radius * radius) {
+                    Some((t0, t1)) => Span {
+                        enter: Boundary { t: t0, norm: flat(at(t0)) / radius },
+                        exit: Boundary { t: t1, norm: flat(at(t1)) / radius },
+                    },
+                    // parallel to the axis, so always or never inside the side
+                    None if fd.norm() == 0. && fo.norm() <= radius => {
+                        use std::f64::INFINITY;
+                        Span {
+                            enter: Boundary { t: -INFINITY, norm: Vector3::zeros() },
+                            exit: Boundary { t: INFINITY, norm: Vector3::zeros() },
+                        }
+                    },
+                    None => return vec![],
+                };
+                let caps = Solid::Cuboid { half: Vector3::new(radius, half_height, radius) };
+                intersection(vec![side], caps.spans(ray))
+            },
+            Solid::Union(ref a, ref b) => union(a.spans(ray), b.spans(ray)),
+            Solid::Intersection(ref a, ref b) => intersection(a.spans(ray), b.spans(ray)),
+            Solid::Difference(ref a, ref b) => difference(a.spans(ray), b.spans(ray)),
+            Solid::Transform(ref iso, ref a) => {
+                let mut spans = a.spans(&transform_ray(&iso.inverse(), *ray));
+                for span in &mut spans {
+                    span.enter.norm = iso * span.enter.norm;
+                    span.exit.norm = iso * span.exit.norm;
+                }
+                spans
+            },
+        }
+    }
+
+    /// a box holding the whole solid
+    pub fn bounds(&self) -> Aabb {
+        match *self {
+            Solid::Sphere { radius } => Aabb::ball(Point3::origin(), radius),
+            Solid::Cuboid { half } => Aabb { min: Point3::from_coordinates(-half), max: Point3::from_coordinates(half) },
+            Solid::Cylinder { radius, half_height } => Aabb {
+                min: Point3::new(-radius, -half_height, -radius),
+                max: Point3::new(radius, half_height, radius),
+            },
+            Solid::Union(ref a, ref b) => a.bounds().union(&b.bounds()),
+            Solid::Intersection(ref a, ref b) => a.bounds().intersection(&b.bounds()),
+            Solid::Difference(ref a, _) => a.bounds(),
+            Solid::Transform(ref iso, ref a) => a.bounds().transformed(iso),
+        }
+    }
+}
+
+/// boundaries closer than this are taken to be the surface a ray starts on
+const EPSILON: f64 = 1e-6;
+
+impl Castable for Solid {
+    fn cast<T>(&self, ray: Ray, data: T) -> Option<Impact<T>> {
+        self.spans(&ray).into_iter()
+            .flat_map(|s| vec![s.enter, s.exit])
+            .find(|b| b.t > EPSILON && b.t.is_finite())
+            .map(|b| Impact { t: b.t, norm: Unit::new_normalize(b.norm), data })
+    }
+}
+
+/// make a solid from an operator name and its arguments
+fn build(call: Call) -> Result<Solid, Error> {
+    use nalg::{Translation3, UnitQuaternion};
+
+    let Call { name, numbers, args } = call;
+    let mut solids = args.into_iter()
+        .map(|a| build(a).map(Box::new))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // combine any number of solids with a binary operator
+    let fold = |solids: Vec<Box<Solid>>, op: &dyn Fn(Box<Solid>, Box<Solid>) -> Solid| {
+        let mut solids = solids.into_iter();
+        let first = *solids.next().unwrap();
+        solids.fold(first, |a, b| op(Box::new(a), b))
+    };
+
+    match (&name[..], &numbers[..], solids.len()) {
+        ("sphere", &[radius], 0) => Ok(Solid::Sphere { radius }),
+        ("box", &[x, y, z], 0) => Ok(Solid::Cuboid { half: Vector3::new(x, y, z) }),
+        ("cylinder", &[radius, half_height], 0) => Ok(Solid::Cylinder { radius, half_height }),
+        ("union", &[], n) if n >= 2 => Ok(fold(solids, &Solid::Union)),
+        ("intersect", &[], n) if n >= 2 => Ok(fold(solids, &Solid::Intersection)),
+        ("subtract", &[], n) if n >= 2 => Ok(fold(solids, &Solid::Difference)),
+        ("translate", &[x, y, z], 1) => Ok(Solid::Transform(
+            Isometry3::from_parts(Translation3::new(x, y, z), UnitQuaternion::identity()),
+            solids.remove(0),
+        )),
+        ("rotate", &[x, y, z], 1) => Ok(Solid::Transform(
+            Isometry3::from_parts(
+                Translation3::identity(),
+                UnitQuaternion::from_euler_angles(x.to_radians(), y.to_radians(), z.to_radians()),
+            ),
+            solids.remove(0),
+        )),
+        _ => Err(format_err!("wrong arguments for \"{}\"", name)),
+    }
+}
+
+impl FromStr for Solid {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Solid, Error> {
+        build(s.parse()?)
+    }
+}
@@ -0,0 +1,134 @@
+//! Denoising of finished frames, guided by the albedo, normal and depth
+//! passes so that edges and texture are kept sharp.
+
+use std::str::FromStr;
+use imgref::ImgVec;
+use palette::LinSrgb;
+use failure::{Error, format_err};
+use aov::AovSample;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Denoiser {
+    /// edge-avoiding à-trous wavelet filter (Dammertz et al. 2010)
+    ATrous,
+    /// joint bilateral filter over a fixed window
+    Bilateral,
+}
+
+impl FromStr for Denoiser {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Denoiser, Error> {
+        match s {
+            "atrous" | "a-trous" => Ok(Denoiser::ATrous),
+            "bilateral" => Ok(Denoiser::Bilateral),
+            _ => Err(format_err!("unknown denoiser \"{}\"", s)),
+        }
+    }
+}
+
+/// how strongly differences in each feature stop the filter
+#[derive(Copy, Clone, Debug)]
+struct Sigmas {
+    color: f32,
+    normal: f32,
+    depth: f32,
+    albedo: f32,
+}
+
+const SIGMAS: Sigmas = Sigmas {
+    color: 0.6,
+    normal: 0.3,
+    depth: 0.1,
+    albedo: 0.1,
+};
+
+fn dist2(a: LinSrgb, b: LinSrgb) -> f32 {
+    let d = a - b;
+    d.red * d.red + d.green * d.green + d.blue * d.blue
+}
+
+/// weight of the pixel `q` when filtering the pixel `p`, ignoring distance
+fn edge_weight(cp: LinSrgb, cq: LinSrgb, p: &AovSample, q: &AovSample, s: Sigmas) -> f32 {
+    let dn: f32 = p.normal.iter().zip(q.normal.iter()).map(|(a, b)| (a - b) * (a - b)).sum();
+    // depth is compared relative to distance, so far away surfaces are not oversmoothed
+    let dd = (p.depth - q.depth) / p.depth.max(q.depth).max(1e-4);
+
+    (-dist2(cp, cq) / (s.color * s.color)
+        - dn / (s.normal * s.normal)
+        - dd * dd / (s.depth * s.depth)
+        - dist2(p.albedo, q.albedo) / (s.albedo * s.albedo)).exp()
+}
+
+/// filter `color`, with every pixel gathering its neighbors at the given offsets
+fn filter(
+    color: &ImgVec<LinSrgb>,
+    features: &ImgVec<AovSample>,
+    offsets: &[(isize, isize, f32)],
+    sigmas: Sigmas,
+) -> ImgVec<LinSrgb> {
+    let (w, h) = (color.width(), color.height());
+    let mut out = Vec::with_capacity(w * h);
+    for y in 0..h {
+        for x in 0..w {
+            let cp = color[(x, y)];
+            let fp = &features[(x, y)];
+
+            let mut sum = LinSrgb::new(0., 0., 0.);
+            let mut weight = 0.;
+            for &(dx, dy, k) in offsets {
+                let (qx, qy) = (x as isize + dx, y as isize + dy);
+                if qx < 0 || qy < 0 || qx >= w as isize || qy >= h as isize { continue }
+                let (qx, qy) = (qx as usize, qy as usize);
+
+                let cq = color[(qx, qy)];
+                let wq = k * edge_weight(cp, cq, fp, &features[(qx, qy)], sigmas);
+                sum = sum + cq * wq;
+                weight += wq;
+            }
+            out.push(if weight > 0. { sum / weight } else { cp });
+        }
+    }
+    ImgVec::new(out, w, h)
+}
+
+/// remove noise from a frame in place, using its AOVs as a guide
+pub fn denoise(method: Denoiser, color: &mut ImgVec<LinSrgb>, features: &ImgVec<AovSample>) {
+    match method {
+        Denoiser::ATrous => {
+            const KERNEL: [f32; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+            const ITERATIONS: u32 = 5;
+
+            let mut sigmas = SIGMAS;
+            for i in 0..ITERATIONS {
+                // spread the same 5x5 kernel out further each pass
+                let step = 1 << i;
+                let mut offsets = Vec::with_capacity(25);
+                for (ky, &wy) in KERNEL.iter().enumerate() {
+                    for (kx, &wx) in KERNEL.iter().enumerate() {
+                        offsets.push(((kx as isize - 2) * step, (ky as isize - 2) * step, wx * wy));
+                    }
+                }
+
+                *color = filter(color, features, &offsets, sigmas);
+
+                // later passes see a smoother image, so must be pickier about color
+                sigmas.color /= 2.;
+            }
+        },
+        Denoiser::Bilateral => {
+            const RADIUS: isize = 6;
+            const SPATIAL: f32 = 3.;
+
+            let mut offsets = Vec::new();
+            for dy in -RADIUS..=RADIUS {
+                for dx in -RADIUS..=RADIUS {
+                    let d2 = (dx * dx + dy * dy) as f32;
+                    offsets.push((dx, dy, (-d2 / (2. * SPATIAL * SPATIAL)).exp()));
+                }
+            }
+
+            *color = filter(color, features, &offsets, SIGMAS);
+        },
+    }
+}
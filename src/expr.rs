@@ -0,0 +1,89 @@
+//! Shapes written as text, like nested function calls.
+
+use std::str::FromStr;
+use failure::{Error, format_err};
+
+/// a call like `translate(0, 1, 0, sphere(2))`, with its arguments split
+/// into numbers and nested calls
+#[derive(Clone, Debug, PartialEq)]
+pub struct Call {
+    pub name: String,
+    pub numbers: Vec<f64>,
+    pub args: Vec<Call>,
+}
+
+struct Parser<'s> {
+    text: &'s str,
+    pos: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn rest(&self) -> &'s str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// take a run of characters matching `f`
+    fn take(&mut self, f: impl Fn(char) -> bool) -> &'s str {
+        self.skip_space();
+        let rest = self.rest();
+        let len = rest.find(|c: char| !f(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Error> {
+        self.skip_space();
+        if self.rest().starts_with(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format_err!("expected '{}' at position {} of \"{}\"", c, self.pos, self.text))
+        }
+    }
+
+    fn call(&mut self) -> Result<Call, Error> {
+        let name = self.take(|c| c.is_ascii_alphanumeric() || c == '_');
+        if name.is_empty() {
+            return Err(format_err!("expected a shape at position {} of \"{}\"", self.pos, self.text))
+        }
+
+        let mut call = Call { name: name.to_string(), numbers: Vec::new(), args: Vec::new() };
+        self.expect('(')?;
+        self.skip_space();
+        if !self.rest().starts_with(')') {
+            loop {
+                self.skip_space();
+                if self.rest().starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '-' || c == '+') {
+                    let number = self.take(|c| c.is_ascii_digit() || c == '.' || c == '-' || c == '+' || c == 'e');
+                    call.numbers.push(number.parse()?);
+                } else {
+                    call.args.push(self.call()?);
+                }
+                self.skip_space();
+                if self.rest().starts_with(',') { self.pos += 1 } else { break }
+            }
+        }
+        self.expect(')')?;
+
+        Ok(call)
+    }
+}
+
+impl FromStr for Call {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Call, Error> {
+        let mut parser = Parser { text: s, pos: 0 };
+        let call = parser.call()?;
+        parser.skip_space();
+        if parser.pos < s.len() {
+            return Err(format_err!("unexpected \"{}\" after shape", parser.rest()))
+        }
+        Ok(call)
+    }
+}
@@ -0,0 +1,85 @@
+//! A minimal OpenEXR writer, for uncompressed 32-bit float scanline images
+//! with any number of channels.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use failure::Error;
+
+/// a single named channel of an image, with one value per pixel
+pub struct Channel {
+    /// full channel name, including the layer (e.g. "normal.X")
+    pub name: String,
+    pub data: Vec<f32>,
+}
+
+impl Channel {
+    pub fn new(name: impl Into<String>, data: Vec<f32>) -> Channel {
+        Channel { name: name.into(), data }
+    }
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// write channels of a `width` by `height` image to an EXR file
+pub fn write(path: impl AsRef<Path>, width: usize, height: usize, mut channels: Vec<Channel>) -> Result<(), Error> {
+    // readers expect channels in alphabetical order
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut header = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+
+    let mut chlist = Vec::new();
+    for ch in &channels {
+        assert_eq!(ch.data.len(), width * height, "channel {} is the wrong size", ch.name);
+        chlist.extend_from_slice(ch.name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&2i32.to_le_bytes()); // FLOAT
+        chlist.extend_from_slice(&[0, 0, 0, 0]); // pLinear, reserved
+        chlist.extend_from_slice(&1i32.to_le_bytes()); // xSampling
+        chlist.extend_from_slice(&1i32.to_le_bytes()); // ySampling
+    }
+    chlist.push(0);
+    attribute(&mut header, "channels", "chlist", &chlist);
+
+    attribute(&mut header, "compression", "compression", &[0]);
+    let mut window = Vec::new();
+    for &v in &[0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_bits().to_le_bytes());
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut header, "screenWindowWidth", "float", &1f32.to_bits().to_le_bytes());
+    header.push(0);
+
+    // every scanline is its own block, preceded by a table of block offsets
+    let line_size = width * channels.len() * 4;
+    let first_line = header.len() + height * 8;
+
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(&header)?;
+    for y in 0..height {
+        out.write_all(&((first_line + y * (line_size + 8)) as u64).to_le_bytes())?;
+    }
+    for y in 0..height {
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&(line_size as i32).to_le_bytes())?;
+        for ch in &channels {
+            for v in &ch.data[y * width..(y + 1) * width] {
+                out.write_all(&v.to_bits().to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,85 @@
+//! Choosing where a camera focuses, so that focus can follow moving subjects.
+
+use std::str::FromStr;
+use nalg::{Point2, Point3, Vector3};
+use failure::{Error, format_err};
+use camera::{Camera, Placed, film_extent};
+use sample::World;
+
+/// distance to focus at when there is nothing to focus on
+pub const FAR: f64 = 1000.;
+
+/// what a camera keeps in focus, parsed from a distance or strings like
+/// "object:NAME", "point:X,Y,Z" or "screen:X,Y"
+#[derive(Clone, Debug, PartialEq)]
+pub enum Focus {
+    /// a fixed distance in front of the camera
+    Distance(f64),
+    /// the center of the object with the given name
+    Object(String),
+    /// a point in the world
+    Point(Point3<f64>),
+    /// whatever surface is seen at a point on the screen, from `(0, 0)` at
+    /// the top left to `(1, 1)` at the bottom right
+    Screen(f64, f64),
+}
+
+fn numbers(s: &str, count: usize) -> Result<Vec<f64>, Error> {
+    let values = s.split(',')
+        .map(|v| v.trim().parse())
+        .collect::<Result<Vec<f64>, _>>()?;
+    if values.len() != count {
+        return Err(format_err!("expected {} comma separated numbers, found \"{}\"", count, s))
+    }
+    Ok(values)
+}
+
+impl FromStr for Focus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Focus, Error> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("object"), Some(name)) => Ok(Focus::Object(name.to_string())),
+            (Some("point"), Some(p)) => {
+                let p = numbers(p, 3)?;
+                Ok(Focus::Point(Point3::new(p[0], p[1], p[2])))
+            },
+            (Some("screen"), Some(p)) => {
+                let p = numbers(p, 2)?;
+                Ok(Focus::Screen(p[0], p[1]))
+            },
+            (Some(d), None) => Ok(Focus::Distance(d.trim().parse()?)),
+            _ => Err(format_err!("unknown focus target \"{}\"", s)),
+        }
+    }
+}
+
+impl Focus {
+    /// distance in front of a camera, on a film with the given aspect ratio,
+    /// at which this target is in focus
+    ///
+    /// The distance is measured along the view direction, since the plane in
+    /// focus faces the camera.
+    pub fn distance<C: Camera<Point2<f64>> + Placed>(&self, cam: &C, aspect: f64, world: &World) -> Result<f64, Error> {
+        let position = cam.position();
+        let forward = position * Vector3::z();
+        let depth = |p: Point3<f64>| (p - position * Point3::origin()).dot(&forward);
+
+        match *self {
+            Focus::Distance(d) => Ok(d),
+            Focus::Object(ref name) => world.objects.iter()
+                .find(|o| o.name.as_ref() == Some(name))
+                .map(|o| depth(o.center()))
+                .ok_or_else(|| format_err!("no object named \"{}\" to focus on", name)),
+            Focus::Point(p) => Ok(depth(p)),
+            Focus::Screen(x, y) => {
+                let extent = film_extent(aspect);
+                let film = Point2::new((x * 2. - 1.) * extent.x, (y * 2. - 1.) * extent.y);
+                Ok(cam.look(film)
+                    .and_then(|ray| world.hit_surface(ray).map(|hit| hit.t * ray.dir.dot(&forward)))
+                    .unwrap_or(FAR))
+            },
+        }
+    }
+}
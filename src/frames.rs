@@ -0,0 +1,87 @@
+use std::str::FromStr;
+use failure::{Error, format_err};
+
+/// an explicit list of frames, parsed from strings like "1,5,10-20"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameList {
+    /// inclusive ranges of frame numbers, sorted and without overlaps, so
+    /// that no frame is rendered twice
+    pub ranges: Vec<(u32, u32)>,
+}
+
+impl FrameList {
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item=u32> + 'a {
+        self.ranges.iter().flat_map(|&(a, b)| a..=b)
+    }
+}
+
+impl FromStr for FrameList {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<FrameList, Error> {
+        let mut ranges = Vec::new();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let range = match part.find('-') {
+                Some(i) => (part[..i].trim().parse()?, part[i + 1..].trim().parse()?),
+                None => { let n = part.parse()?; (n, n) },
+            };
+            if range.0 > range.1 { return Err(format_err!("frame range \"{}\" is backwards", part)) }
+            ranges.push(range);
+        }
+        if ranges.is_empty() { return Err(format_err!("no frames selected")) }
+
+        // merge ranges which overlap or touch
+        ranges.sort();
+        let mut merged: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+        for (a, b) in ranges {
+            match merged.last_mut() {
+                Some(last) if a <= last.1.saturating_add(1) => last.1 = last.1.max(b),
+                _ => merged.push((a, b)),
+            }
+        }
+        Ok(FrameList { ranges: merged })
+    }
+}
+
+/// how many frames to move on between rendered frames, at least 1
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Step(u32);
+
+impl Step {
+    pub fn new(n: u32) -> Result<Step, Error> {
+        match n {
+            0 => Err(format_err!("frame step must be at least 1")),
+            n => Ok(Step(n)),
+        }
+    }
+
+    pub fn get(self) -> u32 { self.0 }
+}
+
+impl FromStr for Step {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Step, Error> {
+        Step::new(s.trim().parse()?)
+    }
+}
+
+/// which frames of an animation should be rendered
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FrameSelection {
+    /// every `step`th frame in `start..end`
+    Range { start: u32, end: u32, step: Step },
+    /// exactly the given frames
+    List(FrameList),
+}
+
+impl FrameSelection {
+    /// get all selected frame numbers in render order
+    pub fn frames(&self) -> Vec<u32> {
+        match self {
+            FrameSelection::Range { start, end, step } =>
+                (*start..*end).step_by(step.get() as usize).collect(),
+            FrameSelection::List(list) => list.iter().collect(),
+        }
+    }
+}
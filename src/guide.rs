@@ -0,0 +1,470 @@
+//! Path guiding, where the path tracer learns where light arrives from at
+//! each part of the scene and aims diffuse reflections that way, after
+//! Müller et al., "Practical Path Guiding for Efficient Light-Transport
+//! Simulation".
+//!
+//! Space is split in half again and again wherever many paths pass, and each
+//! part keeps a quadtree over the directions light arrives from, finest
+//! where most light was found. Before each frame, passes of paths twice as
+//! many as the last are traced from the camera, each guided by what the
+//! passes before it learned, and the last pass is what the frame is
+//! rendered with. Guided directions are mixed with cosine weighted ones, so
+//! light the guide hasn't seen yet is still found.
+//!
+//! The paths of each pass are learned in a fixed number of runs, which are
+//! shared out between the render threads and added up in order, so the
+//! guide comes out the same however many threads there are. A worker trains
+//! once for each frame however many connections it has.
+//!
+//! Paths learning the guide follow light through subsurface objects but not
+//! fog, and only learn how bright light is rather than its color.
+
+use std::f64::consts::PI;
+use std::thread;
+use nalg::{Point3, Vector3, Unit};
+use camera::Ray;
+use bvh::Aabb;
+use sample::{World, reflect, camera_ray};
+use sampler::{Sampler, Independent, hash};
+use spectral::Channels;
+use integrator::diffuse_dir;
+use photon::through;
+use mlt::luminance;
+use pipe::{FrameData, film_point};
+
+/// how much of a quadtree's light a node needs before it is split
+const SPLIT_ENERGY: f64 = 0.01;
+
+/// runs of paths each pass is learned in, however many threads there are
+const RUNS: usize = 16;
+
+/// deepest a quadtree may go
+const MAX_DEPTH: usize = 20;
+
+/// samples a part of space needs in the first pass before it is split,
+/// growing with the square root of the paths in later passes
+const SPLIT_SAMPLES: f64 = 4000.;
+
+/// a direction as a point on the unit square, where equal areas are equal
+/// solid angles
+fn to_square(dir: Unit<Vector3<f64>>) -> (f64, f64) {
+    let u = ((dir.z + 1.) / 2.).max(0.).min(1.);
+    let phi = dir.y.atan2(dir.x);
+    let v = if phi < 0. { phi / (2. * PI) + 1. } else { phi / (2. * PI) };
+    (u, v.min(1.))
+}
+
+/// the direction at a point on the unit square
+fn from_square((u, v): (f64, f64)) -> Unit<Vector3<f64>> {
+    let cos = 2. * u - 1.;
+    let sin = (1. - cos * cos).max(0.).sqrt();
+    let phi = 2. * PI * v;
+    Unit::new_normalize(Vector3::new(sin * phi.cos(), sin * phi.sin(), cos))
+}
+
+/// which quarter of a square a point is in, and where it is in that quarter
+fn quadrant((u, v): (f64, f64)) -> (usize, (f64, f64)) {
+    let (x, u) = if u < 0.5 { (0, u * 2.) } else { (1, u * 2. - 1.) };
+    let (y, v) = if v < 0.5 { (0, v * 2.) } else { (1, v * 2. - 1.) };
+    (x + 2 * y, (u, v))
+}
+
+/// pick one of two parts by their weights, and rescale the value used
+fn choose(u: f64, a: f64, b: f64) -> (usize, f64) {
+    let p = a / (a + b);
+    if u < p { (0, u / p) } else { (1, ((u - p) / (1. - p)).min(1.)) }
+}
+
+/// a node of a quadtree, split into four quarters
+#[derive(Copy, Clone, Debug, Default)]
+struct QuadNode {
+    /// light found arriving through each quarter
+    sums: [f64; 4],
+    /// node further splitting each quarter, or 0 if it isn't
+    children: [usize; 4],
+}
+
+/// how much light arrives from each direction, over the unit square
+#[derive(Clone, Debug)]
+struct DirectionTree {
+    /// nodes, starting with the one covering the whole square
+    nodes: Vec<QuadNode>,
+    /// paths which passed through while learning
+    samples: usize,
+}
+
+impl DirectionTree {
+    /// a tree which has found no light, split into quarters once
+    fn new() -> DirectionTree {
+        DirectionTree { nodes: vec![QuadNode::default()], samples: 0 }
+    }
+
+    fn total(&self) -> f64 {
+        self.nodes[0].sums.iter().sum()
+    }
+
+    /// add light arriving from a direction
+    fn record(&mut self, point: (f64, f64), value: f64) {
+        let (mut n, mut point) = (0, point);
+        loop {
+            let (q, inner) = quadrant(point);
+            self.nodes[n].sums[q] += value;
+            match self.nodes[n].children[q] {
+                0 => return,
+                child => {
+                    n = child;
+                    point = inner;
+                },
+            }
+        }
+    }
+
+    /// a point on the unit square, in proportion to the light found there
+    fn sample(&self, (mut u, mut v): (f64, f64)) -> (f64, f64) {
+        let (mut n, mut origin, mut size) = (0, (0., 0.), 1.);
+        loop {
+            let s = self.nodes[n].sums;
+            let (x, nu) = choose(u, s[0] + s[2], s[1] + s[3]);
+            let (y, nv) = choose(v, s[x], s[x + 2]);
+            u = nu;
+            v = nv;
+            size /= 2.;
+            origin = (origin.0 + x as f64 * size, origin.1 + y as f64 * size);
+            match self.nodes[n].children[x + 2 * y] {
+                0 => return (origin.0 + u * size, origin.1 + v * size),
+                child => n = child,
+            }
+        }
+    }
+
+    /// probability density of sampling a point on the unit square
+    fn pdf(&self, point: (f64, f64)) -> f64 {
+        let (mut n, mut point, mut pdf) = (0, point, 1.);
+        loop {
+            let s = self.nodes[n].sums;
+            let total: f64 = s.iter().sum();
+            if total <= 0. { return 0. }
+            let (q, inner) = quadrant(point);
+            pdf *= 4. * s[q] / total;
+            match self.nodes[n].children[q] {
+                0 => return pdf,
+                child => {
+                    n = child;
+                    point = inner;
+                },
+            }
+        }
+    }
+
+    /// add in what another copy of this tree learned
+    fn absorb(&mut self, other: &DirectionTree) {
+        for (node, theirs) in self.nodes.iter_mut().zip(&other.nodes) {
+            for (sum, their) in node.sums.iter_mut().zip(&theirs.sums) {
+                *sum += their;
+            }
+        }
+        self.samples += other.samples;
+    }
+
+    /// an empty tree to learn with next, split wherever this one found a
+    /// lot of light and merged wherever it found little
+    fn refined(&self) -> DirectionTree {
+        let total = self.total();
+        let mut tree = DirectionTree::new();
+        if total <= 0. { return tree }
+
+        // nodes in the new tree, the node covering the same area in this
+        // one (if it goes that deep), and the light found in it
+        let mut stack = vec![(0, Some(0), total, 1)];
+        while let Some((n, old, energy, depth)) = stack.pop() {
+            let sums = match old {
+                Some(o) => self.nodes[o].sums,
+                // light is spread evenly below the leaves
+                None => [energy / 4.; 4],
+            };
+            for (q, &light) in sums.iter().enumerate() {
+                if light / total <= SPLIT_ENERGY || depth >= MAX_DEPTH { continue }
+                let child = tree.nodes.len();
+                tree.nodes.push(QuadNode::default());
+                tree.nodes[n].children[q] = child;
+                let old_child = old.map(|o| self.nodes[o].children[q]).filter(|&c| c != 0);
+                stack.push((child, old_child, light, depth + 1));
+            }
+        }
+        tree
+    }
+}
+
+/// a part of space, either split in half or with its own directions
+#[derive(Clone, Debug)]
+enum SpatialNode {
+    Split {
+        axis: usize,
+        at: f64,
+        /// nodes below and above the split
+        children: [usize; 2],
+    },
+    Leaf {
+        /// what was learned by the last pass, which paths are guided by
+        sampling: DirectionTree,
+        /// what is being learned by this pass
+        learning: DirectionTree,
+    },
+}
+
+/// where light arrives from, all over the scene
+#[derive(Clone, Debug)]
+pub struct Guide {
+    bounds: Aabb,
+    /// nodes, starting with the one covering all of the bounds
+    nodes: Vec<SpatialNode>,
+    /// how often directions are picked by the guide rather than by
+    /// cos(theta)
+    fraction: f64,
+}
+
+impl Guide {
+    /// a guide which has learned nothing yet, over some part of space
+    fn new(bounds: Aabb, fraction: f64) -> Guide {
+        Guide {
+            bounds,
+            nodes: vec![SpatialNode::Leaf { sampling: DirectionTree::new(), learning: DirectionTree::new() }],
+            fraction,
+        }
+    }
+
+    /// the leaf a point is in
+    fn leaf(&self, point: Point3<f64>) -> usize {
+        let mut n = 0;
+        loop {
+            match self.nodes[n] {
+                SpatialNode::Split { axis, at, children } =>
+                    n = children[if point[axis] < at { 0 } else { 1 }],
+                SpatialNode::Leaf { .. } => return n,
+            }
+        }
+    }
+
+    /// a direction to carry on from a point on a diffuse surface, and its
+    /// probability density, either towards where light was found to arrive
+    /// from or with cos(theta) weighting around the normal
+    ///
+    /// Directions below the surface can be picked, which reflect no light.
+    pub fn sample<S: Sampler>(&self, point: Point3<f64>, norm: Unit<Vector3<f64>>, sampler: &mut S) -> (Unit<Vector3<f64>>, f64) {
+        let tree = match self.nodes[self.leaf(point)] {
+            SpatialNode::Leaf { ref sampling, .. } => sampling,
+            SpatialNode::Split { .. } => unreachable!(),
+        };
+
+        // nothing has been learned about this part of space
+        let fraction = if tree.total() > 0. { self.fraction } else { 0. };
+        let pick = sampler.next();
+        let sample = sampler.next_2d();
+        let dir = if pick < fraction { from_square(tree.sample(sample)) } else { diffuse_dir(norm, sample) };
+
+        // the chance of either way picking the direction, where squares
+        // are spread over the whole sphere
+        let cos = dir.dot(&norm).max(0.);
+        let pdf = fraction * tree.pdf(to_square(dir)) / (4. * PI) + (1. - fraction) * cos / PI;
+        (dir, pdf)
+    }
+
+    /// learn of light arriving at a point from a direction, found with
+    /// some probability density
+    fn record(&mut self, point: Point3<f64>, dir: Unit<Vector3<f64>>, light: f64, pdf: f64) {
+        let n = self.leaf(point);
+        if let SpatialNode::Leaf { ref mut learning, .. } = self.nodes[n] {
+            learning.samples += 1;
+            if light > 0. && pdf > 0. {
+                learning.record(to_square(dir), light / pdf);
+            }
+        }
+    }
+
+    /// add in what another copy of this guide learned during the same pass
+    fn absorb(&mut self, other: &Guide) {
+        for (node, theirs) in self.nodes.iter_mut().zip(&other.nodes) {
+            if let (&mut SpatialNode::Leaf { ref mut learning, .. }, &SpatialNode::Leaf { learning: ref their, .. }) = (node, theirs) {
+                learning.absorb(their);
+            }
+        }
+    }
+
+    /// guide paths with what was learned in a pass, and get ready to learn
+    /// again, splitting space where many paths passed
+    fn refine(&mut self, pass: usize) {
+        for node in &mut self.nodes {
+            if let SpatialNode::Leaf { ref mut sampling, ref mut learning } = *node {
+                *sampling = learning.clone();
+            }
+        }
+
+        let limit = SPLIT_SAMPLES * 2f64.powf(pass as f64 / 2.);
+        let bounds = self.bounds;
+        self.split(0, bounds, 0, limit);
+
+        for node in &mut self.nodes {
+            if let SpatialNode::Leaf { ref sampling, ref mut learning } = *node {
+                *learning = sampling.refined();
+            }
+        }
+    }
+
+    /// split a part of space in half, on each axis in turn, for as long as
+    /// too many paths passed through it
+    fn split(&mut self, n: usize, bounds: Aabb, depth: usize, limit: f64) {
+        let children = match self.nodes[n] {
+            SpatialNode::Split { children, .. } => children,
+            SpatialNode::Leaf { ref sampling, ref learning } => {
+                if (learning.samples as f64) <= limit { return }
+
+                // both halves start out with everything learned so far, and
+                // half of the paths each
+                let mut half = learning.clone();
+                half.samples /= 2;
+                let leaf = SpatialNode::Leaf { sampling: sampling.clone(), learning: half };
+                let first = self.nodes.len();
+                self.nodes.push(leaf.clone());
+                self.nodes.push(leaf);
+                let axis = depth % 3;
+                let at = (bounds.min[axis] + bounds.max[axis]) / 2.;
+                self.nodes[n] = SpatialNode::Split { axis, at, children: [first, first + 1] };
+                [first, first + 1]
+            },
+        };
+
+        if let SpatialNode::Split { axis, at, .. } = self.nodes[n] {
+            let (mut below, mut above) = (bounds, bounds);
+            below.max[axis] = at;
+            above.min[axis] = at;
+            self.split(children[0], below, depth + 1, limit);
+            self.split(children[1], above, depth + 1, limit);
+        }
+    }
+
+    /// follow a path from the camera, guided by what has been learned so
+    /// far, and learn from the light it finds
+    fn learn<S: Sampler>(&mut self, world: &World, mut ray: Ray, sampler: &mut S, bounce_limit: usize) {
+        /// a diffuse reflection along the path
+        struct Vertex {
+            point: Point3<f64>,
+            dir: Unit<Vector3<f64>>,
+            pdf: f64,
+            /// how much of the light after the reflection reaches the camera
+            beta: f64,
+            /// light arriving from the direction taken
+            light: f64,
+        }
+
+        let mut beta = 1.;
+        let mut vertices: Vec<Vertex> = Vec::new();
+        for bounce in 0..=bounce_limit {
+            let hit = world.hit_surface(ray);
+            let emission = match hit {
+                Some(ref hit) => luminance(hit.data.1.material.emission),
+                None => luminance(world.ambient),
+            };
+
+            // light given off here arrives at every reflection before it
+            for v in &mut vertices {
+                v.light += emission * beta / v.beta;
+            }
+
+            let hit = match hit {
+                Some(hit) if bounce < bounce_limit => hit,
+                _ => break,
+            };
+            let (_, o) = hit.data;
+            let point = ray.origin + hit.t * ray.dir.unwrap();
+            let refl = o.material.reflectivity as f64;
+
+            // the probability of either kind of reflection cancels out its filter
+            ray = if sampler.next() < refl {
+                Ray::new(point, reflect(ray.dir, hit.norm)).at_time(ray.time)
+            } else if let Some(ref sub) = o.material.subsurface {
+                match through(world, ray, &hit, sub, sampler, Channels::Rgb) {
+                    Some((out, weight)) => {
+                        beta *= luminance(weight);
+                        out
+                    },
+                    None => break,
+                }
+            } else {
+                let (dir, pdf) = self.sample(point, hit.norm, sampler);
+                let cos = dir.dot(&hit.norm);
+                if cos <= 0. || pdf <= 0. { break }
+                beta *= cos / (PI * pdf);
+                vertices.push(Vertex { point, dir, pdf, beta, light: 0. });
+                Ray::new(point, dir).at_time(ray.time)
+            };
+            if beta <= 0. { break }
+        }
+
+        for v in vertices {
+            self.record(v.point, v.dir, v.light, v.pdf);
+        }
+    }
+}
+
+/// how paths learn where light arrives from before each frame
+#[derive(Copy, Clone, Debug)]
+pub struct Guiding {
+    /// passes of paths traced, each twice as many as the last
+    pub passes: usize,
+    /// paths traced in the first pass
+    pub paths: usize,
+    /// how often directions are picked by the guide rather than by
+    /// cos(theta)
+    pub fraction: f64,
+    pub seed: u64,
+}
+
+impl Guiding {
+    /// learn where light arrives from in a frame of some size, along paths
+    /// from its camera, shared out between threads
+    pub fn train(&self, frame: &FrameData, size: (usize, usize), frame_num: u32, bounce_limit: usize, threads: usize) -> Guide {
+        // unbounded objects such as planes would leave nothing to split
+        let bounds = frame.world.objects.iter()
+            .map(|o| o.bounds())
+            .filter(|b| b.is_finite())
+            .fold(Aabb::empty(), |a, b| a.union(&b));
+        let bounds = if bounds.is_finite() { bounds } else { Aabb::ball(Point3::origin(), 1.) };
+        let mut guide = Guide::new(bounds, self.fraction);
+
+        let threads = threads.max(1).min(RUNS);
+        for pass in 0..self.passes {
+            // each run of paths is learned with its own copy of the guide,
+            // and the copies are added up in order, so that the sums don't
+            // depend on which thread learned what
+            let paths = self.paths << pass;
+            let mut runs: Vec<(usize, Guide)> = {
+                let guide = &guide;
+                thread::scope(|scope| {
+                    let handles: Vec<_> = (0..threads).map(|t| scope.spawn(move || {
+                        (t..RUNS).step_by(threads).map(|run| {
+                            let mut part = guide.clone();
+                            // learning shouldn't use the same random numbers as camera paths
+                            let mut sampler = Independent::new(hash(&[self.seed, pass as u64]), frame_num);
+                            for i in paths * run / RUNS..paths * (run + 1) / RUNS {
+                                sampler.start((i, pass), 0);
+                                let (u, v) = sampler.next_2d();
+                                let film = film_point(size, u * size.0 as f64, v * size.1 as f64);
+                                if let Some((ray, _)) = camera_ray(&*frame.cam, film, &mut sampler, &frame.params) {
+                                    part.learn(&frame.world, ray, &mut sampler, bounce_limit);
+                                }
+                            }
+                            (run, part)
+                        }).collect::<Vec<_>>()
+                    })).collect();
+                    handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+                })
+            };
+            runs.sort_by_key(|&(run, _)| run);
+            for &(_, ref part) in &runs {
+                guide.absorb(part);
+            }
+            guide.refine(pass);
+        }
+        guide
+    }
+}
@@ -0,0 +1,344 @@
+//! Integrators, which find the light arriving at the camera along a ray.
+//!
+//! Besides the path tracer there are a few simpler integrators, which are
+//! quicker or show a single part of the picture, to help set up and debug
+//! scenes.
+
+use std::f64::consts::PI;
+use std::str::FromStr;
+use std::sync::Arc;
+use imgref::ImgVec;
+use nalg::{Vector3, Unit};
+use palette::LinSrgb;
+use failure::{Error, format_err};
+use camera::{Ray, Impact};
+use sample::{World, reflect, cosine_weighted_hemi};
+use sampler::Sampler;
+use spectral::Channels;
+use stats::{MulBackPath, ForPath};
+use bdpt::Bidirectional;
+use photon::PhotonMapping;
+use mlt::Metropolis;
+use particle::LightTracer;
+use guide::Guiding;
+use pipe::{Tile, FrameData};
+
+/// light reaching the camera, split by how many times it bounced
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Radiance {
+    pub emission: LinSrgb,
+    pub direct: LinSrgb,
+    pub indirect: LinSrgb,
+}
+
+impl Radiance {
+    pub fn add(&mut self, bounces: usize, light: LinSrgb) {
+        match bounces {
+            0 => self.emission = self.emission + light,
+            1 => self.direct = self.direct + light,
+            _ => self.indirect = self.indirect + light,
+        }
+    }
+
+    pub fn total(&self) -> LinSrgb {
+        self.emission + self.direct + self.indirect
+    }
+}
+
+pub trait Integrator: Send + Sync {
+    /// get ready to render a frame of some size, before any of its tiles are,
+    /// with as many threads as tiles are rendered with
+    fn prepare(&self, _frame: &mut FrameData, _size: (usize, usize), _frame_num: u32, _threads: usize) {}
+
+    /// light arriving along a camera ray, carried in the given channels
+    fn radiance<S: Sampler>(&self, world: &World, ray: Ray, sampler: &mut S, channels: Channels) -> Radiance;
+
+    /// light landing anywhere on the film rather than through one pixel,
+    /// found while rendering a tile of a frame of some size
+    ///
+    /// Each tile finds a share of the light in proportion to its size, as a
+    /// buffer the size of the frame.
+    fn splat(&self, _tile: &Tile, _size: (usize, usize)) -> Option<ImgVec<LinSrgb>> {
+        None
+    }
+
+    /// whether all light is splatted, so pixels needn't be sampled at all
+    fn splats_only(&self) -> bool {
+        false
+    }
+}
+
+/// follows paths backwards from the camera
+#[derive(Copy, Clone, Debug)]
+pub struct PathTracer {
+    /// most times light may bounce
+    pub bounce_limit: usize,
+    /// learn where light comes from before each frame, and aim diffuse
+    /// reflections that way
+    pub guiding: Option<Guiding>,
+}
+
+impl Integrator for PathTracer {
+    fn prepare(&self, frame: &mut FrameData, size: (usize, usize), frame_num: u32, threads: usize) {
+        if let Some(ref guiding) = self.guiding {
+            let guide = guiding.train(frame, size, frame_num, self.bounce_limit, threads);
+            frame.world.guide = Some(Arc::new(guide));
+        }
+    }
+
+    fn radiance<S: Sampler>(&self, world: &World, ray: Ray, sampler: &mut S, channels: Channels) -> Radiance {
+        let path = world.sample(ray, MulBackPath::new(), self.bounce_limit, sampler, channels);
+        let mut light = Radiance::default();
+        light.add(path.bounces(), path.lum());
+        light
+    }
+}
+
+/// normal facing back towards where a ray came from
+pub fn facing(ray: &Ray, norm: Unit<Vector3<f64>>) -> Unit<Vector3<f64>> {
+    if ray.dir.dot(&norm) < 0. { norm } else { -norm }
+}
+
+/// a random direction around a normal, with cos(theta) weighting
+pub fn diffuse_dir(norm: Unit<Vector3<f64>>, sample: (f64, f64)) -> Unit<Vector3<f64>> {
+    Unit::new_unchecked(Impact { t: 0., norm, data: () }.surface() * cosine_weighted_hemi(sample).unwrap())
+}
+
+/// only light which reaches the first surface seen straight from an emitter
+/// or the sky, ignoring media and light beneath surfaces
+#[derive(Copy, Clone, Debug)]
+pub struct DirectLighting;
+
+impl DirectLighting {
+    /// light given off by whatever a ray hits first, unless it could have
+    /// been aimed at instead
+    fn seen(&self, world: &World, ray: Ray, channels: Channels, aimed: bool) -> LinSrgb {
+        match world.hit_surface(ray) {
+            None => channels.radiance(world.ambient),
+            Some(hit) => {
+                let (n, o) = hit.data;
+                if aimed && world.lights().pdf(&world.objects, n) > 0. {
+                    LinSrgb::new(0., 0., 0.)
+                } else {
+                    channels.radiance(o.material.emission)
+                }
+            },
+        }
+    }
+}
+
+impl Integrator for DirectLighting {
+    fn radiance<S: Sampler>(&self, world: &World, ray: Ray, sampler: &mut S, channels: Channels) -> Radiance {
+        let mut light = Radiance::default();
+        let hit = match world.hit_surface(ray) {
+            Some(hit) => hit,
+            None => {
+                light.add(0, channels.radiance(world.ambient));
+                return light
+            },
+        };
+        let (_, o) = hit.data;
+        let point = ray.origin + hit.t * ray.dir.unwrap();
+        let norm = facing(&ray, hit.norm);
+        light.add(0, channels.radiance(o.material.emission));
+
+        // whatever is seen in the mirror-like part of the surface
+        let refl = o.material.reflectivity;
+        if refl > 0. {
+            let mirror = Ray::new(point, reflect(ray.dir, hit.norm)).at_time(ray.time);
+            light.add(1, self.seen(world, mirror, channels, false) * refl);
+        }
+        if refl >= 1. { return light }
+
+        // aim at an emitter, which shines only outwards
+        let diffuse = 1. - refl as f64;
+        if let Some(l) = world.lights().sample_point(&world.objects, ray.time, sampler) {
+            let d = l.point - point;
+            let d2 = d.norm_squared();
+            let dir = d / d2.sqrt();
+            let (cos_here, cos_there) = (norm.dot(&dir), -l.norm.dot(&dir));
+            if cos_here > 0. && cos_there > 0. && world.visible(point, l.point, ray.time) {
+                let emission = channels.radiance(world.objects[l.object].material.emission);
+                light.add(1, emission * (diffuse / PI * cos_here * cos_there / d2 / l.pdf) as f32);
+            }
+        }
+
+        // anything else could be in any direction
+        let scattered = Ray::new(point, diffuse_dir(norm, sampler.next_2d())).at_time(ray.time);
+        light.add(1, self.seen(world, scattered, channels, true) * diffuse as f32);
+        light
+    }
+}
+
+/// how much of the sky each surface sees, nearby objects blocking it
+#[derive(Copy, Clone, Debug)]
+pub struct AmbientOcclusion {
+    /// objects further than this don't block anything
+    pub distance: f64,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance<S: Sampler>(&self, world: &World, ray: Ray, sampler: &mut S, channels: Channels) -> Radiance {
+        let open = match world.hit_surface(ray) {
+            None => true,
+            Some(hit) => {
+                let point = ray.origin + hit.t * ray.dir.unwrap();
+                let dir = diffuse_dir(facing(&ray, hit.norm), sampler.next_2d());
+                world.hit_surface(Ray::new(point, dir).at_time(ray.time))
+                    .map_or(true, |i| i.t > self.distance)
+            },
+        };
+        let v = if open { 1. } else { 0. };
+        let mut light = Radiance::default();
+        light.add(0, channels.reflectance(LinSrgb::new(v, v, v)));
+        light
+    }
+}
+
+/// surface normals of the first thing seen, with each axis from -1 to 1
+/// shown as a channel from 0 to 1
+#[derive(Copy, Clone, Debug)]
+pub struct Normals;
+
+impl Integrator for Normals {
+    fn radiance<S: Sampler>(&self, world: &World, ray: Ray, _: &mut S, channels: Channels) -> Radiance {
+        let mut light = Radiance::default();
+        if let Some(hit) = world.hit_surface(ray) {
+            let n = hit.norm.map(|v| (v * 0.5 + 0.5) as f32);
+            light.add(0, channels.reflectance(LinSrgb::new(n.x, n.y, n.z)));
+        }
+        light
+    }
+}
+
+/// distance to the first thing seen, from white up close to black at `range`
+#[derive(Copy, Clone, Debug)]
+pub struct Depth {
+    pub range: f64,
+}
+
+impl Integrator for Depth {
+    fn radiance<S: Sampler>(&self, world: &World, ray: Ray, _: &mut S, channels: Channels) -> Radiance {
+        let mut light = Radiance::default();
+        if let Some(hit) = world.hit_surface(ray) {
+            let v = (1. - hit.t / self.range).max(0.) as f32;
+            light.add(0, channels.reflectance(LinSrgb::new(v, v, v)));
+        }
+        light
+    }
+}
+
+/// which integrator to render with
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IntegratorKind {
+    Path,
+    Guided,
+    Bdpt,
+    Direct,
+    AmbientOcclusion,
+    Normals,
+    Depth,
+    PhotonMapping,
+    Caustics,
+    Metropolis,
+    MetropolisBdpt,
+    Light,
+}
+
+impl IntegratorKind {
+    /// whether light can land anywhere on the frame, which only local
+    /// threads can add up
+    pub fn splats(&self) -> bool {
+        match *self {
+            IntegratorKind::Metropolis | IntegratorKind::MetropolisBdpt | IntegratorKind::Light => true,
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IntegratorKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<IntegratorKind, Error> {
+        match s {
+            "path" => Ok(IntegratorKind::Path),
+            "guided" => Ok(IntegratorKind::Guided),
+            "bdpt" => Ok(IntegratorKind::Bdpt),
+            "direct" => Ok(IntegratorKind::Direct),
+            "ao" => Ok(IntegratorKind::AmbientOcclusion),
+            "normals" => Ok(IntegratorKind::Normals),
+            "depth" => Ok(IntegratorKind::Depth),
+            "ppm" => Ok(IntegratorKind::PhotonMapping),
+            "caustics" => Ok(IntegratorKind::Caustics),
+            "mlt" => Ok(IntegratorKind::Metropolis),
+            "mlt-bdpt" => Ok(IntegratorKind::MetropolisBdpt),
+            "light" => Ok(IntegratorKind::Light),
+            _ => Err(format_err!("unknown integrator \"{}\"", s)),
+        }
+    }
+}
+
+/// any of the integrators, picked while running
+#[derive(Copy, Clone, Debug)]
+pub enum AnyIntegrator {
+    Path(PathTracer),
+    Bdpt(Bidirectional),
+    Direct(DirectLighting),
+    AmbientOcclusion(AmbientOcclusion),
+    Normals(Normals),
+    Depth(Depth),
+    PhotonMapping(PhotonMapping),
+    Metropolis(Metropolis<PathTracer>),
+    MetropolisBdpt(Metropolis<Bidirectional>),
+    Light(LightTracer),
+}
+
+impl Integrator for AnyIntegrator {
+    fn prepare(&self, frame: &mut FrameData, size: (usize, usize), frame_num: u32, threads: usize) {
+        match *self {
+            AnyIntegrator::Path(ref i) => i.prepare(frame, size, frame_num, threads),
+            AnyIntegrator::Bdpt(ref i) => i.prepare(frame, size, frame_num, threads),
+            AnyIntegrator::Direct(ref i) => i.prepare(frame, size, frame_num, threads),
+            AnyIntegrator::AmbientOcclusion(ref i) => i.prepare(frame, size, frame_num, threads),
+            AnyIntegrator::Normals(ref i) => i.prepare(frame, size, frame_num, threads),
+            AnyIntegrator::Depth(ref i) => i.prepare(frame, size, frame_num, threads),
+            AnyIntegrator::PhotonMapping(ref i) => i.prepare(frame, size, frame_num, threads),
+            AnyIntegrator::Metropolis(ref i) => i.prepare(frame, size, frame_num, threads),
+            AnyIntegrator::MetropolisBdpt(ref i) => i.prepare(frame, size, frame_num, threads),
+            AnyIntegrator::Light(ref i) => i.prepare(frame, size, frame_num, threads),
+        }
+    }
+
+    fn radiance<S: Sampler>(&self, world: &World, ray: Ray, sampler: &mut S, channels: Channels) -> Radiance {
+        match *self {
+            AnyIntegrator::Path(ref i) => i.radiance(world, ray, sampler, channels),
+            AnyIntegrator::Bdpt(ref i) => i.radiance(world, ray, sampler, channels),
+            AnyIntegrator::Direct(ref i) => i.radiance(world, ray, sampler, channels),
+            AnyIntegrator::AmbientOcclusion(ref i) => i.radiance(world, ray, sampler, channels),
+            AnyIntegrator::Normals(ref i) => i.radiance(world, ray, sampler, channels),
+            AnyIntegrator::Depth(ref i) => i.radiance(world, ray, sampler, channels),
+            AnyIntegrator::PhotonMapping(ref i) => i.radiance(world, ray, sampler, channels),
+            AnyIntegrator::Metropolis(ref i) => i.radiance(world, ray, sampler, channels),
+            AnyIntegrator::MetropolisBdpt(ref i) => i.radiance(world, ray, sampler, channels),
+            AnyIntegrator::Light(ref i) => i.radiance(world, ray, sampler, channels),
+        }
+    }
+
+    fn splat(&self, tile: &Tile, size: (usize, usize)) -> Option<ImgVec<LinSrgb>> {
+        match *self {
+            AnyIntegrator::Metropolis(ref i) => i.splat(tile, size),
+            AnyIntegrator::MetropolisBdpt(ref i) => i.splat(tile, size),
+            AnyIntegrator::Light(ref i) => i.splat(tile, size),
+            _ => None,
+        }
+    }
+
+    fn splats_only(&self) -> bool {
+        match *self {
+            AnyIntegrator::Metropolis(ref i) => i.splats_only(),
+            AnyIntegrator::MetropolisBdpt(ref i) => i.splats_only(),
+            AnyIntegrator::Light(ref i) => i.splats_only(),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! Emitters which light can be followed forwards from, or aimed at directly.
+//!
+//! Only spheres can be picked, since points on them are easy to choose
+//! evenly. Other emitters still light the scene, but can only be found by
+//! chance.
+
+use std::f64::consts::PI;
+use nalg::{Point3, Vector3, Unit};
+use camera::Impact;
+use sample::{Object, cosine_weighted_hemi};
+use shape::Shape;
+use sampler::Sampler;
+
+/// a point picked on an emitter
+#[derive(Copy, Clone, Debug)]
+pub struct LightPoint {
+    /// index of the emitting object
+    pub object: usize,
+    pub point: Point3<f64>,
+    /// outward normal, which is the only way the emitter shines
+    pub norm: Unit<Vector3<f64>>,
+    /// density of picking this point, per unit area
+    pub pdf: f64,
+}
+
+/// emitters, and how likely each is to be picked
+#[derive(Clone, Debug)]
+pub struct Lights {
+    /// object indices and the probability of picking each
+    picks: Vec<(usize, f64)>,
+}
+
+fn area(radius: f64) -> f64 {
+    4. * PI * radius * radius
+}
+
+impl Lights {
+    /// find the emitting spheres among some objects, to be picked by how
+    /// much light they give off
+    pub fn new(objects: &[Object]) -> Lights {
+        let power: Vec<_> = objects.iter().enumerate()
+            .filter(|&(_, o)| o.medium.is_none())
+            .filter_map(|(n, o)| match *o.shape {
+                Shape::Sphere(ref s) => {
+                    let e = o.material.emission;
+                    let brightness = (e.red + e.green + e.blue) as f64 / 3.;
+                    if brightness > 0. { Some((n, brightness * area(s.radius()))) } else { None }
+                },
+                _ => None,
+            })
+            .collect();
+
+        let total: f64 = power.iter().map(|&(_, p)| p).sum();
+        Lights { picks: power.into_iter().map(|(n, p)| (n, p / total)).collect() }
+    }
+
+    /// density of picking a point on an object, per unit area, which is 0 if
+    /// the object is never picked
+    pub fn pdf(&self, objects: &[Object], object: usize) -> f64 {
+        match (self.picks.iter().find(|&&(n, _)| n == object), &*objects[object].shape) {
+            (Some(&(_, prob)), &Shape::Sphere(ref s)) => prob / area(s.radius()),
+            _ => 0.,
+        }
+    }
+
+    /// pick a point on an emitter, where it is at a point in time
+    pub fn sample_point<S: Sampler>(&self, objects: &[Object], time: f64, sampler: &mut S) -> Option<LightPoint> {
+        let u = sampler.next();
+        let mut sum = 0.;
+        let &(n, prob) = self.picks.iter()
+            .find(|&&(_, p)| { sum += p; u < sum })
+            .or_else(|| self.picks.last())?;
+
+        let o = &objects[n];
+        let sphere = match *o.shape {
+            Shape::Sphere(ref s) => s,
+            _ => return None,
+        };
+
+        // uniformly random over the sphere
+        let (u, v) = sampler.next_2d();
+        let z = 1. - 2. * u;
+        let r = (1. - z * z).max(0.).sqrt();
+        let phi = 2. * PI * v;
+        let out = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+        let iso = o.transform_at(time);
+        Some(LightPoint {
+            object: n,
+            point: iso * (sphere.center + out * sphere.radius()),
+            norm: Unit::new_unchecked(iso * out),
+            pdf: prob / area(sphere.radius()),
+        })
+    }
+
+    /// pick a point on an emitter and a direction light leaves it in, along
+    /// with the density of that direction per unit solid angle
+    pub fn sample<S: Sampler>(&self, objects: &[Object], time: f64, sampler: &mut S) -> Option<(LightPoint, Unit<Vector3<f64>>, f64)> {
+        let light = self.sample_point(objects, time, sampler)?;
+
+        // light leaves with a cosine falloff
+        let cwh = cosine_weighted_hemi(sampler.next_2d());
+        let dir = Unit::new_unchecked(Impact { t: 0., norm: light.norm, data: () }.surface() * cwh.unwrap());
+        Some((light, dir, dir.dot(&light.norm) / PI))
+    }
+}
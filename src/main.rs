@@ -18,94 +18,519 @@ pub mod sample;
 pub mod camera;
 pub mod stats;
 pub mod pipe;
+pub mod frames;
+pub mod net;
+pub mod sampler;
+pub mod aov;
+pub mod exr;
+pub mod output;
+pub mod denoise;
+pub mod aperture;
+pub mod motion;
+pub mod focus;
+pub mod bvh;
+pub mod shape;
+pub mod sdf;
+pub mod csg;
+pub mod expr;
+pub mod medium;
+pub mod subsurface;
+pub mod spectral;
+pub mod bdpt;
+pub mod lights;
+pub mod integrator;
+pub mod photon;
+pub mod mlt;
+pub mod particle;
+pub mod guide;
 
 use failure::Error;
 
-#[derive(StructOpt, Debug)]
+#[derive(StructOpt, Debug, Clone)]
 #[structopt(name="sidequest")]
 struct Params {
     #[structopt(short="t", long="threads", help="override worker count")]
     threads: Option<usize>,
-    #[structopt(short="w", long="width", default_value="512", help="width of output frames")]
+    #[structopt(short="w", long="width", default_value="512", help="width of output frames (of each eye, for stereo)")]
     width: u32,
-    #[structopt(short="h", long="height", default_value="512", help="height of output frames")]
+    #[structopt(short="h", long="height", default_value="512", help="height of output frames (of each eye, for stereo)")]
     height: u32,
     #[structopt(short="s", long="samples", default_value="1000", help="number of samples per pixel")]
     samples: usize,
-    #[structopt(short="f", long="frames", default_value="30", help="number of frames to render")]
+    #[structopt(short="f", long="frames", default_value="30", help="number of frames in the animation")]
     frames: u32,
+    #[structopt(long="start", default_value="0", help="first frame to render")]
+    start: u32,
+    #[structopt(long="end", help="frame to stop rendering at (exclusive, defaults to --frames)")]
+    end: Option<u32>,
+    #[structopt(long="step", default_value="1", help="render only every nth frame")]
+    step: frames::Step,
+    #[structopt(long="select", help="explicit list of frames to render (e.g. \"1,5,10-20\")")]
+    select: Option<frames::FrameList>,
     #[structopt(long="tilesize", default_value="64", help="width/height of a single render unit")]
     tile_size: u32,
     #[structopt(long="bounces", default_value="12", help="maximum length of light path")]
     bounce_limit: usize,
+    #[structopt(long="camera", default_value="perspective", help="camera projection (perspective, orthographic, equirectangular, fisheye, equisolid or cubemap)")]
+    projection: camera::Projection,
+    #[structopt(long="fov", default_value="45", help="perspective field of view, in degrees")]
+    fov: f64,
+    #[structopt(long="fov-axis", default_value="vertical", help="axis the field of view is measured along (horizontal, vertical or diagonal)")]
+    fov_axis: camera::FovAxis,
+    #[structopt(long="sensor", help="sensor size in millimeters (e.g. \"36x24\"), overriding --fov")]
+    sensor: Option<Sensor>,
+    #[structopt(long="focus", default_value="14", help="what to focus on: a distance, \"object:NAME\", \"point:X,Y,Z\" or \"screen:X,Y\" (from 0,0 at the top left to 1,1)")]
+    focus: focus::Focus,
+    #[structopt(long="focal-length", default_value="50", help="lens focal length in millimeters, used with --sensor and --fstop")]
+    focal_length: f64,
+    #[structopt(long="fstop", help="lens f-number, with the world measured in meters (overrides --aperture)")]
+    fstop: Option<f64>,
+    #[structopt(long="aperture", default_value="0.2", help="radius of the lens aperture, in world units")]
+    aperture: f64,
+    #[structopt(long="blades", default_value="0", help="number of aperture blades, or 0 for a round aperture")]
+    blades: u32,
+    #[structopt(long="blade-rotation", default_value="0", help="rotation of the aperture blades, in degrees")]
+    blade_rotation: f64,
+    #[structopt(long="aperture-image", help="grayscale image to use as the aperture shape, for custom bokeh")]
+    aperture_image: Option<String>,
+    #[structopt(long="cat-eye", default_value="0", help="how strongly the lens barrel clips the aperture towards the edges")]
+    cat_eye: f64,
+    #[structopt(long="view-extent", default_value="6", help="half the height of an orthographic view, in world units")]
+    view_extent: f64,
+    #[structopt(long="fisheye-fov", default_value="180", help="angle across a fisheye image circle, in degrees")]
+    fisheye_fov: f64,
+    #[structopt(long="stereo", help="render a stereo pair with parallel or toe-in eyes")]
+    stereo: Option<camera::StereoMode>,
+    #[structopt(long="stereo-layout", default_value="sbs", help="stereo output layout (sbs, tb or separate)")]
+    stereo_layout: camera::StereoLayout,
+    #[structopt(long="interocular", default_value="0.25", help="distance between stereo eyes, in world units")]
+    interocular: f64,
+    #[structopt(long="convergence", default_value="14", help="distance at which stereo eyes converge")]
+    convergence: f64,
+    #[structopt(long="shutter-open", default_value="0", help="time the shutter opens, in frames relative to each frame")]
+    shutter_open: f64,
+    #[structopt(long="shutter-close", default_value="0", help="time the shutter closes, in frames relative to each frame (e.g. 0.5 for a 180 degree shutter)")]
+    shutter_close: f64,
+    #[structopt(long="shutter-curve", default_value="box", help="how the shutter opens and closes (box, triangle or smooth)")]
+    shutter_curve: motion::ShutterCurve,
+    #[structopt(long="orbit", default_value="0", help="degrees the camera circles around the scene over the whole animation")]
+    orbit: f64,
+    #[structopt(long="sdf", help="add a distance field shape to the scene (e.g. \"translate(0, 3, 0, torus(2, 0.5))\")")]
+    sdf: Vec<sdf::Sdf>,
+    #[structopt(long="csg", help="add a solid made of exact boolean operations to the scene (e.g. \"subtract(box(1, 1, 1), sphere(1.3))\")")]
+    csg: Vec<csg::Solid>,
+    #[structopt(long="shape-emission", default_value="0", help="light emitted by --sdf and --csg shapes, as r,g,b")]
+    shape_emission: subsurface::Rgb,
+    #[structopt(long="shape-reflectivity", default_value="0.25", help="fraction of light --sdf and --csg shapes reflect like a mirror rather than diffusely")]
+    shape_reflectivity: f32,
+    #[structopt(long="rings", help="add a ring of beads around the base and a tilted copy of it, both instances of one group")]
+    rings: bool,
+    #[structopt(long="fog", default_value="0", help="density of fog filling the scene, as the fraction of light it stops per unit")]
+    fog: f64,
+    #[structopt(long="fog-albedo", default_value="0.9", help="fraction of light stopped by fog which is scattered rather than absorbed")]
+    fog_albedo: f64,
+    #[structopt(long="fog-g", default_value="0", help="fog phase function asymmetry, from -1 (backwards) to 1 (forwards)")]
+    fog_g: f64,
+    #[structopt(long="volume", help="fill the named object with smoke instead of giving it a surface")]
+    volume: Vec<String>,
+    #[structopt(long="volume-density", default_value="4", help="densest fraction of light stopped per unit by volumes")]
+    volume_density: f64,
+    #[structopt(long="volume-albedo", default_value="0.9", help="fraction of light stopped by volumes which is scattered rather than absorbed")]
+    volume_albedo: f64,
+    #[structopt(long="volume-g", default_value="0.3", help="volume phase function asymmetry, from -1 (backwards) to 1 (forwards)")]
+    volume_g: f64,
+    #[structopt(long="volume-grid", help="density grid file to fill volumes with, instead of generated smoke")]
+    volume_grid: Option<String>,
+    #[structopt(long="subsurface", help="make the named object scatter light beneath its surface, like wax or skin")]
+    subsurface: Vec<String>,
+    #[structopt(long="mean-free-path", default_value="1,0.4,0.2", help="average distance light travels between scattering inside subsurface objects, as r,g,b")]
+    mean_free_path: subsurface::Rgb,
+    #[structopt(long="subsurface-albedo", default_value="0.99", help="fraction of light scattered rather than absorbed inside subsurface objects, as r,g,b")]
+    subsurface_albedo: subsurface::Rgb,
+    #[structopt(long="ior", default_value="1.4", help="index of refraction of the surface of subsurface objects, or \"cauchy:A,B\" or \"sellmeier:B1,B2,B3,C1,C2,C3\" for one that disperses light")]
+    ior: spectral::Ior,
+    #[structopt(long="spectral", help="trace light of a few wavelengths along each path instead of RGB, which is needed for dispersion")]
+    spectral: bool,
+    #[structopt(long="seed", default_value="0", help="seed for all random sampling decisions")]
+    seed: u64,
+    #[structopt(long="sampler", default_value="independent", help="sample distribution (independent, stratified, halton, sobol or bluenoise)")]
+    sampler: sampler::SamplerKind,
+    #[structopt(long="integrator", default_value="path", help="how light is found (path, guided, bdpt, direct, ao, normals, depth, ppm, caustics, mlt, mlt-bdpt or light)")]
+    integrator: integrator::IntegratorKind,
+    #[structopt(long="ao-distance", default_value="2", help="furthest distance that blocks light with the ao integrator")]
+    ao_distance: f64,
+    #[structopt(long="depth-range", default_value="40", help="distance shown as black by the depth integrator")]
+    depth_range: f64,
+    #[structopt(long="photons", default_value="50000", help="photons traced in each pass, by the ppm and caustics integrators")]
+    photons: usize,
+    #[structopt(long="photon-passes", default_value="8", help="passes of photons traced for each frame")]
+    photon_passes: usize,
+    #[structopt(long="photon-radius", default_value="0.1", help="radius photons are gathered over in the first pass")]
+    photon_radius: f64,
+    #[structopt(long="photon-alpha", default_value="0.7", help="how slowly the photon radius shrinks between passes, from 0 to 1")]
+    photon_alpha: f64,
+    #[structopt(long="mlt-bootstrap", default_value="100000", help="paths traced to find the brightness of each frame, by the mlt integrators")]
+    mlt_bootstrap: usize,
+    #[structopt(long="mlt-chains", default_value="1000", help="Markov chains run over each frame")]
+    mlt_chains: usize,
+    #[structopt(long="mlt-sigma", default_value="0.01", help="size of the small steps between paths")]
+    mlt_sigma: f64,
+    #[structopt(long="mlt-large-step", default_value="0.3", help="probability of picking a whole new path instead of a small step")]
+    mlt_large_step: f64,
+    #[structopt(long="guide-passes", default_value="6", help="passes of paths learning where light comes from before each frame, by the guided integrator")]
+    guide_passes: usize,
+    #[structopt(long="guide-paths", default_value="8192", help="paths traced in the first pass, doubling in each pass after")]
+    guide_paths: usize,
+    #[structopt(long="guide-fraction", default_value="0.5", help="how often diffuse reflections are aimed by what was learned, from 0 to 1")]
+    guide_fraction: f64,
+    #[structopt(long="aovs", help="extra passes to output (depth, normal, albedo, id, direct, indirect, emission or all)")]
+    aovs: Option<aov::AovList>,
+    #[structopt(long="separate-aovs", help="write passes to their own files, even for EXR output")]
+    separate_aovs: bool,
+    #[structopt(long="denoise", help="filter finished frames to remove noise (atrous or bilateral)")]
+    denoise: Option<denoise::Denoiser>,
+    #[structopt(long="listen", help="hand out tiles to workers connecting to this address")]
+    listen: Option<String>,
     #[structopt(name="OUTPUT", help="output image filename where \"%n\" is the frame number")]
-    output: String,
+    output: Option<String>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() -> Result<(), Error> {
-    use nalg::{Isometry3, Point3, Vector3};
-    use camera::{DefocusCamera, PerspectiveCamera};
-    use sample::{World, Object};
-    use std::f64::consts::{FRAC_PI_4, PI};
+/// width and height of a camera sensor, parsed from strings like "36x24"
+#[derive(Copy, Clone, Debug)]
+struct Sensor(f64, f64);
+
+impl std::str::FromStr for Sensor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Sensor, Error> {
+        use failure::format_err;
+
+        let mut parts = s.split('x');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(w), Some(h), None) => Ok(Sensor(w.trim().parse()?, h.trim().parse()?)),
+            _ => Err(format_err!("sensor size should look like \"36x24\"")),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+enum Command {
+    #[structopt(name="worker", about="render tiles for a coordinator started with --listen")]
+    Worker {
+        #[structopt(name="COORDINATOR", help="address of the coordinator")]
+        addr: String,
+        #[structopt(long="patience", default_value="30", help="seconds to keep retrying a lost coordinator")]
+        patience: u64,
+    },
+}
+
+/// the integrator picked by the parameters
+fn integrator(params: &Params) -> integrator::AnyIntegrator {
+    use integrator::*;
+
+    match params.integrator {
+        IntegratorKind::Path => AnyIntegrator::Path(PathTracer { bounce_limit: params.bounce_limit, guiding: None }),
+        IntegratorKind::Guided => AnyIntegrator::Path(PathTracer {
+            bounce_limit: params.bounce_limit,
+            guiding: Some(guide::Guiding {
+                passes: params.guide_passes,
+                paths: params.guide_paths,
+                fraction: params.guide_fraction,
+                seed: params.seed,
+            }),
+        }),
+        IntegratorKind::Bdpt => AnyIntegrator::Bdpt(bdpt::Bidirectional { bounce_limit: params.bounce_limit }),
+        IntegratorKind::Direct => AnyIntegrator::Direct(DirectLighting),
+        IntegratorKind::AmbientOcclusion => AnyIntegrator::AmbientOcclusion(AmbientOcclusion { distance: params.ao_distance }),
+        IntegratorKind::Normals => AnyIntegrator::Normals(Normals),
+        IntegratorKind::Depth => AnyIntegrator::Depth(Depth { range: params.depth_range }),
+        IntegratorKind::PhotonMapping | IntegratorKind::Caustics => AnyIntegrator::PhotonMapping(photon::PhotonMapping {
+            bounce_limit: params.bounce_limit,
+            photons: params.photons,
+            passes: params.photon_passes,
+            radius: params.photon_radius,
+            alpha: params.photon_alpha,
+            caustics: params.integrator == IntegratorKind::Caustics,
+            seed: params.seed,
+        }),
+        IntegratorKind::Metropolis => AnyIntegrator::Metropolis(mlt::Metropolis {
+            inner: PathTracer { bounce_limit: params.bounce_limit, guiding: None },
+            bootstrap: params.mlt_bootstrap,
+            chains: params.mlt_chains,
+            sigma: params.mlt_sigma,
+            large_step: params.mlt_large_step,
+        }),
+        IntegratorKind::MetropolisBdpt => AnyIntegrator::MetropolisBdpt(mlt::Metropolis {
+            inner: bdpt::Bidirectional { bounce_limit: params.bounce_limit },
+            bootstrap: params.mlt_bootstrap,
+            chains: params.mlt_chains,
+            sigma: params.mlt_sigma,
+            large_step: params.mlt_large_step,
+        }),
+        IntegratorKind::Light => AnyIntegrator::Light(particle::LightTracer { bounce_limit: params.bounce_limit }),
+    }
+}
+
+/// create a function building the world for each frame
+fn scene(params: &Params) -> Result<impl FnMut(u32) -> Result<Option<pipe::FrameData>, Error>, Error> {
+    use nalg::{Isometry3, Point3, Translation3, Vector3, UnitQuaternion};
+    use camera::*;
+    use motion::{Shutter, Motion};
+    use sample::{World, Object, Material};
+    use shape::{Shape, Group};
+    use sdf::SdfShape;
+    use medium::{Medium, Density, DensityGrid};
+    use subsurface::Subsurface;
+    use bvh::Aabb;
     use failure::format_err;
-    use indicatif::{ProgressBar, ProgressStyle};
-    use sdl2::rect::Rect;
-    use sdl2::event::Event;
-    use sdl2::keyboard::Keycode;
-    use structopt::StructOpt;
-    use std::collections::HashMap;
-    use palette::{Pixel, LinSrgb, named as colors};
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+    use palette::{LinSrgb, named as colors};
 
-    // parse args
-    let params = Params::from_args();
-    let threads = params.threads.unwrap_or(num_cpus::get());
     let frame_count = params.frames;
+    // named "sdf1", "csg1" and so on, so they can be made subsurface too
+    let extra_shapes: Vec<_> = params.sdf.iter().enumerate()
+        .map(|(i, s)| (format!("sdf{}", i + 1), Shape::Sdf(SdfShape::new(s.clone()))))
+        .chain(params.csg.iter().enumerate().map(|(i, s)| (format!("csg{}", i + 1), Shape::Csg(s.clone()))))
+        .map(|(name, shape)| (name, Arc::new(shape)))
+        .collect();
+    let shape_material = Material::new(params.shape_emission.0, params.shape_reflectivity);
+    // a ring of beads, shared by every object it makes up
+    let beads = match params.rings {
+        true => Some(Arc::new(Shape::Group(Group::new((0..12).map(|i| {
+            let angle = 2. * PI * i as f64 / 12.;
+            (Isometry3::translation(6. * angle.cos(), 0., 6. * angle.sin()), Shape::Sphere(Sphere::new(Point3::origin(), 0.3)))
+        }).collect())))),
+        false => None,
+    };
+    let projection = params.projection;
+    let fog = match params.fog {
+        f if f > 0. => Some(Medium::new(f, params.fog_albedo, params.fog_g, Density::Homogeneous)),
+        _ => None,
+    };
+    let volumes = params.volume.clone();
+    // grids are stretched over each volume's object every frame
+    let unit = Aabb { min: Point3::new(-1., -1., -1.), max: Point3::new(1., 1., 1.) };
+    let volume_grid = match params.volume_grid {
+        Some(ref path) => DensityGrid::open(path, unit)?,
+        None => DensityGrid::smoke(unit, 48, params.seed),
+    };
+    let (volume_density, volume_albedo, volume_g) = (params.volume_density, params.volume_albedo, params.volume_g);
+    let subsurfaces = params.subsurface.clone();
+    let subsurface = Subsurface {
+        mean_free_path: params.mean_free_path.0,
+        albedo: params.subsurface_albedo.0,
+        ior: params.ior,
+        g: 0.,
+    };
+    let view_extent = params.view_extent;
+    let fisheye_fov = params.fisheye_fov;
+    let stereo = params.stereo;
+    let stereo_layout = params.stereo_layout;
+    let interocular = params.interocular;
+    let convergence = params.convergence;
+    let orbit = params.orbit.to_radians();
+    let eye_size = (params.width as f64, params.height as f64);
+    let aspect = eye_size.0 / eye_size.1;
+    let fov = match params.sensor {
+        Some(Sensor(width, height)) => FieldOfView::Sensor { width, height, focal_length: params.focal_length },
+        None => FieldOfView::Angle(params.fov_axis, params.fov.to_radians()),
+    };
+    let shape = match (&params.aperture_image, params.blades) {
+        (Some(path), _) => ApertureShape::Image(Arc::new(ApertureImage::open(path)?)),
+        (None, 0) => ApertureShape::Circle,
+        (None, blades) => ApertureShape::Polygon { blades, rotation: params.blade_rotation.to_radians() },
+    };
+    let aperture = Aperture {
+        cat_eye: params.cat_eye,
+        ..match params.fstop {
+            Some(fstop) => Aperture::from_fstop(params.focal_length, fstop, shape),
+            None => Aperture { radius: params.aperture, shape, cat_eye: 0. },
+        }
+    };
+    // light can only be traced back to a single point on a flat film
+    let pinhole = projection == Projection::Perspective && aperture.radius == 0. && aperture.cat_eye == 0.;
+    let still = params.orbit == 0. || params.shutter_close <= params.shutter_open;
+    if params.integrator == integrator::IntegratorKind::Light && (!pinhole || !still || params.stereo.is_some()) {
+        return Err(format_err!("the light tracer needs a single perspective camera with --aperture 0, which doesn't move while the shutter is open"));
+    }
+    let focus = params.focus.clone();
+    let shutter = Shutter {
+        open: params.shutter_open,
+        close: params.shutter_close,
+        curve: params.shutter_curve,
+    };
     let sample_params = sample::SampleParams {
         samples: params.samples,
-        bounce_limit: params.bounce_limit,
+        seed: params.seed,
+        sampler: params.sampler,
+        // the denoiser is guided by AOVs
+        aovs: params.aovs.is_some() || params.denoise.is_some(),
+        shutter,
+        spectral: params.spectral,
     };
-    let render_params = pipe::RenderParams {
-        width: params.width as usize,
-        height: params.height as usize,
-        tile_size: params.tile_size as usize,
-        tile_queue: threads * 2,
-        threads: threads,
-    };
-    let output_template = params.output;
-    let output_path = move |n| output_template.replace("%n", &format!("{}", n));
 
-    // function to create world for each frame
-    let per_frame_world = move |index: u32| -> Result<Option<pipe::FrameData>, Error> {
+    Ok(move |index: u32| -> Result<Option<pipe::FrameData>, Error> {
         // check end of animation or close
         if index >= frame_count { return Ok(None) }
 
-        // create camera
-        let angle = 2. * PI * (index as f64 / frame_count  as f64);
-        let cam = PerspectiveCamera::new(
+        // objects at a point in time, in frames relative to this one
+        let objects_at = |time: f64| {
+            let angle = 2. * PI * ((index as f64 + time) / frame_count as f64);
+            vec![
+                //Format: (x, y, z, radius, emmisivity(r,g,b), reflectivity)
+                Object::new(0., -2., 0., 3., LinSrgb::new(0.894, 0.345, 0.925) * 0.25f32, 0.5).named("base"),
+                Object::new(0., 3., 0., 1.5, LinSrgb::new(0.8, 1., 0.8) * 0.9f32, 0.75).named("top"),
+                Object::new(4., -2.25 * angle.sin(), 0., 1., LinSrgb::new(1.0, 0.2, 0.2) * 0.75f32 * (((angle.cos() + 1.) as f32) / 2f32), 0.95).named("red"),
+                Object::new(-4., 2.25 * angle.sin(), 0., 1., LinSrgb::new(0.2, 0.2, 1.) * 0.75f32 * (((angle.sin() + 1.) as f32) / 2f32), 0.95).named("blue"),
+                Object::new(4. * angle.sin(), 0., 4. * angle.cos(), 1., LinSrgb::new(0., 0., 0.), 0.95).named("mirror"),
+                Object::new(-4. * angle.sin(), 0., -4. * angle.cos(), 1., LinSrgb::new(0., 0., 0.), 0.05).named("matte"),
+            ]
+        };
+
+        // move objects between where they are when the shutter opens and closes
+        let mut objects = objects_at(0.);
+        if shutter.close > shutter.open {
+            let open = objects_at(shutter.open);
+            let close = objects_at(shutter.close);
+            for ((o, a), b) in objects.iter_mut().zip(open).zip(close) {
+                let now = o.center();
+                o.motion = Some(Motion::linear(
+                    (shutter.open, Translation3::from_vector(a.center() - now)),
+                    (shutter.close, Translation3::from_vector(b.center() - now)),
+                ));
+            }
+        }
+
+        // extra shapes don't move
+        for &(ref name, ref shape) in &extra_shapes {
+            objects.push(Object::instance(
+                shape.clone(),
+                Isometry3::identity(),
+                shape_material,
+            ).named(name));
+        }
+
+        // the beads circle the base, with a tilted copy of them
+        if let Some(ref beads) = beads {
+            let ring = Object::instance(
+                beads.clone(),
+                Isometry3::translation(0., -1., 0.),
+                Material::new(LinSrgb::new(0., 0., 0.), 0.5),
+            ).named("ring");
+            let tilted = ring.instanced(Isometry3::new(Vector3::new(0., -1., 0.), Vector3::new(0.3, 0., 0.)), None).named("tilted");
+            objects.push(ring);
+            objects.push(tilted);
+        }
+
+        // fill volumes with smoke, stretched over where they are now
+        if let Some(name) = volumes.iter().find(|&n| !objects.iter().any(|o| o.name.as_ref() == Some(n))) {
+            return Err(format_err!("no object named \"{}\" to fill with smoke", name))
+        }
+        for o in objects.iter_mut().filter(|o| o.name.as_ref().map_or(false, |n| volumes.contains(n))) {
+            let bounds = o.shape.bounds().transformed(&o.transform);
+            o.medium = Some(Medium::new(
+                volume_density,
+                volume_albedo,
+                volume_g,
+                Density::Grid(Arc::new(volume_grid.with_bounds(bounds))),
+            ));
+        }
+
+        // light scattering beneath a surface is reflected by the boundary
+        // itself, so there is no separate mirror reflection
+        if let Some(name) = subsurfaces.iter().find(|&n| !objects.iter().any(|o| o.name.as_ref() == Some(n))) {
+            return Err(format_err!("no object named \"{}\" to make subsurface", name))
+        }
+        for o in objects.iter_mut().filter(|o| o.name.as_ref().map_or(false, |n| subsurfaces.contains(n))) {
+            o.material.reflectivity = 0.;
+            o.material.subsurface = Some(subsurface);
+        }
+
+        // create world
+        let mut world = World::new(
+            objects,
+            colors::DARKSLATEGREY.into_format::<f32>().into_linear() * 0.4,
+            0.00001,
+        );
+        world.fog = fog.clone();
+
+        // create camera, where it is at a point in time in frames relative to this one
+        let position_at = |time: f64| {
+            let angle = orbit * (index as f64 + time) / frame_count as f64;
+            let turn = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), angle);
             Isometry3::new_observer_frame(
-                &Point3::new(12., 8., 12.),
+                &(turn * Point3::new(12., 8., 12.)),
                 &Point3::new(0., 0., 0.),
                 &Vector3::new(0., 1., 0.),
-            ),
-            FRAC_PI_4,
-            0.1,
-            100.,
-        );
-        let cam = DefocusCamera::new(cam, 14.);
+            )
+        };
+        let position = position_at(0.);
+        let perspective = PerspectiveCamera::with_fov(position, fov, aspect, 0.1, 100.);
+        let orthographic = OrthographicCamera::new(position, view_extent, aspect);
 
-        // create world
-        let world = World {
-            objects: vec![
-                //Format: (x, y, z, radius, emmisivity(r,g,b), reflectivity)
-                Object::new(0., -2., 0., 3., LinSrgb::new(0.894, 0.345, 0.925) * 0.25f32, 0.5),
-                Object::new(0., 3., 0., 1.5, LinSrgb::new(0.8, 1., 0.8) * 0.9f32, 0.75),
-                Object::new(4., -2.25 * angle.sin(), 0., 1., LinSrgb::new(1.0, 0.2, 0.2) * 0.75f32 * (((angle.cos() + 1.) as f32) / 2f32), 0.95),
-                Object::new(-4., 2.25 * angle.sin(), 0., 1., LinSrgb::new(0.2, 0.2, 1.) * 0.75f32 * (((angle.sin() + 1.) as f32) / 2f32), 0.95),
-                Object::new(4. * angle.sin(), 0., 4. * angle.cos(), 1., LinSrgb::new(0., 0., 0.), 0.95),
-                Object::new(-4. * angle.sin(), 0., -4. * angle.cos(), 1., LinSrgb::new(0., 0., 0.), 0.05),
-            ],
-            ambient: colors::DARKSLATEGREY.into_format::<f32>().into_linear() * 0.4,
-            margin: 0.00001,
+        // focus from the center, so that stereo eyes agree
+        let focal_distance = match projection {
+            Projection::Perspective => focus.distance(&perspective, aspect, &world)?,
+            Projection::Orthographic => focus.distance(&orthographic, aspect, &world)?,
+            // pinhole cameras are in focus everywhere
+            _ => focus::FAR,
+        };
+        type SceneCamera = Box<dyn Camera<CameraSample> + Send + Sync>;
+
+        // camera for an eye moved sideways from the center (positive is left)
+        let eye = |offset: f64| -> SceneCamera {
+            let mode = stereo.unwrap_or(StereoMode::Parallel);
+            match projection {
+                Projection::Perspective => Box::new(DefocusCamera::new(
+                    EyeCamera::new(&perspective, mode, offset, convergence),
+                    focal_distance,
+                    aperture.clone(),
+                )),
+                Projection::Orthographic => Box::new(DefocusCamera::new(
+                    EyeCamera::new(&orthographic, mode, offset, convergence),
+                    focal_distance,
+                    aperture.clone(),
+                )),
+                Projection::Equirectangular => Box::new(Pinhole(EquirectangularCamera {
+                    position,
+                    eye_offset: offset,
+                })),
+                Projection::Fisheye(mapping) => Box::new(Pinhole(EyeCamera::new(
+                    &FisheyeCamera { position, fov: fisheye_fov.to_radians(), mapping },
+                    mode,
+                    offset,
+                    convergence,
+                ))),
+                Projection::Cubemap =>
+                    Box::new(Pinhole(EyeCamera::new(&CubemapCamera { position }, mode, offset, convergence))),
+            }
+        };
+
+        let cam: SceneCamera = match stereo {
+            None => eye(0.),
+            Some(_) => Box::new(StereoRig {
+                left: eye(interocular / 2.),
+                right: eye(-interocular / 2.),
+                layout: stereo_layout,
+                eye_size,
+            }),
+        };
+
+        // move the camera from where it was placed while the shutter is open
+        let cam: SceneCamera = if orbit != 0. && shutter.close > shutter.open {
+            let moved = |time| position_at(time) * position.inverse();
+            Box::new(MovingCamera {
+                base: cam,
+                motion: Motion {
+                    start: (shutter.open, moved(shutter.open)),
+                    end: (shutter.close, moved(shutter.close)),
+                },
+            })
+        } else {
+            cam
         };
 
 
@@ -114,14 +539,81 @@ fn main() -> Result<(), Error> {
             world,
             cam,
             params: sample_params,
+            splats: pipe::Splats::default(),
         }))
+    })
+}
+
+fn main() -> Result<(), Error> {
+    use failure::format_err;
+    use indicatif::{ProgressBar, ProgressStyle};
+    use sdl2::rect::Rect;
+    use sdl2::event::Event;
+    use sdl2::keyboard::Keycode;
+    use structopt::StructOpt;
+    use std::collections::HashMap;
+    use palette::Pixel;
+    use std::time::Duration;
+
+    // parse args
+    let params = Params::from_args();
+    let threads = params.threads.unwrap_or(num_cpus::get());
+
+    // render for someone else
+    if let Some(Command::Worker { addr, patience }) = params.command.clone() {
+        return net::run_worker(&addr, threads, Duration::from_secs(patience), |args| {
+            let params = Params::from_iter_safe(args)?;
+            Ok((scene(&params)?, integrator(&params)))
+        })
+    }
+
+    // accept workers
+    let remote = match params.listen {
+        Some(_) if params.integrator.splats() =>
+            return Err(format_err!("integrators which splat light over the frame can't hand tiles out to workers")),
+        Some(ref addr) => Some(net::Coordinator::bind(addr.as_str(), std::env::args().collect())?),
+        None => None,
+    };
+
+    let frame_count = params.frames;
+    let selection = match params.select.clone() {
+        Some(list) => frames::FrameSelection::List(list),
+        None => frames::FrameSelection::Range {
+            start: params.start,
+            end: params.end.unwrap_or(frame_count),
+            step: params.step,
+        },
     };
+    let frame_nums = selection.frames();
+    if let Some(n) = frame_nums.iter().find(|&&n| n >= frame_count) {
+        return Err(format_err!("frame {} is past the end of the {} frame animation", n, frame_count));
+    }
+    // stereo renders both eyes into the same frame
+    let (across, down) = match params.stereo {
+        Some(_) => params.stereo_layout.grid(),
+        None => (1, 1),
+    };
+    let (width, height) = (params.width * across as u32, params.height * down as u32);
+    let render_params = pipe::RenderParams {
+        width: width as usize,
+        height: height as usize,
+        tile_size: params.tile_size as usize,
+        tile_queue: threads * 2,
+        threads: threads,
+    };
+    let output_template = params.output.clone()
+        .ok_or_else(|| format_err!("an OUTPUT filename is required"))?;
+    let output_path = move |n| output_template.replace("%n", &format!("{}", n));
+    let aovs = params.aovs.clone().map(|a| a.0).unwrap_or_default();
+
+    // function to create world for each frame
+    let per_frame_world = scene(&params)?;
 
     // create preview window
     let sdl = sdl2::init().map_err(|err| format_err!("Could not initialize SDL: {}", err))?;
     let mut events = sdl.event_pump().map_err(|err| format_err!("Could get SDL events: {}", err))?;
     let video = sdl.video().map_err(|err| format_err!("Could get SDL video: {}", err))?;
-    let window = video.window("Sidequest Render Preview", params.width, params.height).build()?;
+    let window = video.window("Sidequest Render Preview", width, height).build()?;
     let mut canvas = window.into_canvas().build()?;
     canvas.clear();
     canvas.present();
@@ -137,7 +629,7 @@ fn main() -> Result<(), Error> {
 
     // setup progress bar
     let sty = ProgressStyle::default_bar().template("[{eta}] {wide_bar} {pos}/{len}");
-    let tiles_bar = ProgressBar::new(render_params.tiles_per_frame() as u64 * frame_count as u64);
+    let tiles_bar = ProgressBar::new(render_params.tiles_per_frame() as u64 * frame_nums.len() as u64);
     tiles_bar.set_style(sty);
     tiles_bar.tick();
 
@@ -156,13 +648,20 @@ fn main() -> Result<(), Error> {
                 .or_insert_with(|| render_params.uninitialized_frame());
             frame.tile_ready(&tile);
             done = frame.is_done();
-            if done { image::save_buffer(
-                output_path(tile.frame_num),
-                Pixel::into_raw_slice(&frame.buf.buf),
-                frame.buf.width() as u32,
-                frame.buf.height() as u32,
-                image::ColorType::RGBA(8),
-            )? }
+            if done {
+                if let (Some(method), Some(features)) = (params.denoise, frame.aovs.as_ref()) {
+                    denoise::denoise(method, &mut frame.buf, features);
+                }
+                let path = output_path(tile.frame_num);
+                match (params.stereo, params.stereo_layout) {
+                    (Some(_), camera::StereoLayout::Separate) => {
+                        let (w, h) = (params.width as usize, params.height as usize);
+                        output::save(&output::suffixed_path(&path, "left"), &frame.crop(0, 0, w, h), &aovs, params.separate_aovs)?;
+                        output::save(&output::suffixed_path(&path, "right"), &frame.crop(w, 0, w, h), &aovs, params.separate_aovs)?;
+                    },
+                    _ => output::save(&path, frame, &aovs, params.separate_aovs)?,
+                }
+            }
         }
         if done { frames.remove(&tile.frame_num); }
 
@@ -171,10 +670,11 @@ fn main() -> Result<(), Error> {
         let height = tile.buf.height() as u32;
         let texrect = Rect::new(0, 0, width, height);
         let canrect = Rect::new(tile.left as i32, tile.top as i32, width, height);
-        let bytes = Pixel::into_raw_slice(&tile.buf.buf);
+        let pixels: Vec<_> = tile.buf.pixels().map(output::to_srgba).collect();
+        let bytes = Pixel::into_raw_slice(&pixels);
 
         // update texture
-        tile_texture.update(Some(texrect), bytes, tile.buf.stride() * 4)?;
+        tile_texture.update(Some(texrect), bytes, tile.buf.width() * 4)?;
 
         // copy texture to screen
         canvas.copy(
@@ -191,6 +691,8 @@ fn main() -> Result<(), Error> {
 
     // run render pipeline
     pipe::render_pipeline(
+        // frame numbers to render
+        frame_nums,
         // create frames to render
         per_frame_world,
         // use rendered tiles
@@ -212,6 +714,10 @@ fn main() -> Result<(), Error> {
         100,
         // render options
         render_params,
+        // find light reaching each pixel
+        integrator(&params),
+        // remote workers
+        remote,
     )?;
 
     // done!
@@ -0,0 +1,298 @@
+//! Participating media, like fog and smoke, which light can scatter inside
+//! of rather than only at surfaces.
+//!
+//! Distances through media are picked by delta tracking against a majorant:
+//! tentative collisions are found as if the whole medium were as dense as its
+//! densest point, and each one is then decided to be absorption, scattering,
+//! or a null collision which the light passes straight through.
+
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use nalg::{Point3, Vector3, Unit};
+use palette::LinSrgb;
+use failure::{Error, format_err};
+use camera::Ray;
+use bvh::Aabb;
+use sampler::{Sampler, hash};
+use stats::BackPath;
+
+/// densities at the points of a regular grid, stretched over a box
+#[derive(Clone, Debug)]
+pub struct DensityGrid {
+    pub bounds: Aabb,
+    size: (usize, usize, usize),
+    values: Vec<f32>,
+    max: f64,
+}
+
+impl DensityGrid {
+    /// a grid of values, with x changing fastest
+    pub fn new(bounds: Aabb, size: (usize, usize, usize), values: Vec<f32>) -> DensityGrid {
+        assert_eq!(values.len(), size.0 * size.1 * size.2, "density grid is the wrong size");
+        let max = values.iter().cloned().fold(0., f32::max) as f64;
+        DensityGrid { bounds, size, values, max }
+    }
+
+    /// read a grid from a file of three little-endian `u32` sizes, followed
+    /// by a little-endian `f32` for each point, with x changing fastest
+    pub fn open(path: impl AsRef<Path>, bounds: Aabb) -> Result<DensityGrid, Error> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        let word = |i: usize| -> Result<u32, Error> {
+            let b = bytes.get(i * 4..i * 4 + 4).ok_or_else(|| format_err!("density grid is cut short"))?;
+            Ok(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+        };
+
+        let size = (word(0)? as usize, word(1)? as usize, word(2)? as usize);
+        let values = (0..size.0 * size.1 * size.2)
+            .map(|i| word(3 + i).map(f32::from_bits))
+            .collect::<Result<_, _>>()?;
+        Ok(DensityGrid::new(bounds, size, values))
+    }
+
+    /// a puff of smoke filling a box, made of a few octaves of value noise
+    /// fading out towards the edges
+    pub fn smoke(bounds: Aabb, resolution: usize, seed: u64) -> DensityGrid {
+        let lattice = |x: i64, y: i64, z: i64, octave: u64| {
+            (hash(&[seed, octave, x as u64, y as u64, z as u64]) >> 11) as f64 / (1u64 << 53) as f64
+        };
+        let noise = |p: Vector3<f64>, octave: u64| {
+            let base = p.map(f64::floor);
+            let f = (p - base).map(|v| v * v * (3. - 2. * v));
+            let mut sum = 0.;
+            for corner in 0..8 {
+                let (dx, dy, dz) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+                let w = if dx == 1 { f.x } else { 1. - f.x }
+                    * if dy == 1 { f.y } else { 1. - f.y }
+                    * if dz == 1 { f.z } else { 1. - f.z };
+                sum += w * lattice(base.x as i64 + dx, base.y as i64 + dy, base.z as i64 + dz, octave);
+            }
+            sum
+        };
+
+        let n = resolution.max(2);
+        let mut values = Vec::with_capacity(n * n * n);
+        for z in 0..n {
+            for y in 0..n {
+                for x in 0..n {
+                    // position in [-1, 1] across the box
+                    let p = Vector3::new(x as f64, y as f64, z as f64) * (2. / (n - 1) as f64)
+                        - Vector3::repeat(1.);
+                    let fbm: f64 = (0..4).map(|o| noise(p * (3. * (1 << o) as f64) + Vector3::repeat(17.), o) / (1 << o) as f64).sum();
+                    let falloff = (1. - p.norm()).max(0.);
+                    values.push((fbm * falloff).max(0.) as f32);
+                }
+            }
+        }
+
+        // densest point is 1
+        let max = values.iter().cloned().fold(0., f32::max);
+        if max > 0. {
+            for v in &mut values { *v /= max }
+        }
+        DensityGrid::new(bounds, (n, n, n), values)
+    }
+
+    /// the same grid, stretched over a different box
+    pub fn with_bounds(&self, bounds: Aabb) -> DensityGrid {
+        DensityGrid { bounds, ..self.clone() }
+    }
+
+    /// density at a point, blended between the nearest grid points
+    pub fn density(&self, p: Point3<f64>) -> f64 {
+        let (nx, ny, nz) = self.size;
+        let extent = self.bounds.max - self.bounds.min;
+        let rel = p - self.bounds.min;
+        let scaled = Vector3::new(
+            rel.x / extent.x * (nx - 1) as f64,
+            rel.y / extent.y * (ny - 1) as f64,
+            rel.z / extent.z * (nz - 1) as f64,
+        );
+        if scaled.iter().any(|v| *v < 0.) || scaled.x > (nx - 1) as f64 || scaled.y > (ny - 1) as f64 || scaled.z > (nz - 1) as f64 {
+            return 0.
+        }
+
+        let base = scaled.map(f64::floor);
+        let f = scaled - base;
+        let at = |x: usize, y: usize, z: usize| self.values[(z.min(nz - 1) * ny + y.min(ny - 1)) * nx + x.min(nx - 1)] as f64;
+        let (x, y, z) = (base.x as usize, base.y as usize, base.z as usize);
+        let mut sum = 0.;
+        for corner in 0..8 {
+            let (dx, dy, dz) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let w = if dx == 1 { f.x } else { 1. - f.x }
+                * if dy == 1 { f.y } else { 1. - f.y }
+                * if dz == 1 { f.z } else { 1. - f.z };
+            sum += w * at(x + dx, y + dy, z + dz);
+        }
+        sum
+    }
+}
+
+/// how the density of a medium varies through space
+#[derive(Clone, Debug)]
+pub enum Density {
+    /// the same everywhere
+    Homogeneous,
+    Grid(Arc<DensityGrid>),
+}
+
+/// what happened to light travelling through a medium
+#[derive(Copy, Clone, Debug)]
+pub enum Event {
+    /// it scattered at this distance along the ray
+    Scatter(f64),
+    Absorb,
+    /// it made it all the way through
+    Pass,
+}
+
+/// sample a Henyey-Greenstein phase function around a direction
+///
+/// Positive `g` scatters mostly forwards, negative mostly backwards.
+pub fn henyey_greenstein(dir: Unit<Vector3<f64>>, g: f64, (u, v): (f64, f64)) -> Unit<Vector3<f64>> {
+    let cos_theta = if g.abs() < 1e-3 {
+        1. - 2. * u
+    } else {
+        let s = (1. - g * g) / (1. - g + 2. * g * u);
+        (1. + g * g - s * s) / (2. * g)
+    };
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * PI * v;
+
+    // any two directions perpendicular to `dir`
+    let helper = if dir.x.abs() > 0.8 { Vector3::y() } else { Vector3::x() };
+    let tan = dir.cross(&helper).normalize();
+    let bitan = dir.cross(&tan);
+    Unit::new_normalize(
+        tan * (sin_theta * phi.cos()) + bitan * (sin_theta * phi.sin()) + dir.unwrap() * cos_theta
+    )
+}
+
+fn average(c: LinSrgb) -> f64 {
+    (c.red + c.green + c.blue) as f64 / 3.
+}
+
+fn max_channel(c: LinSrgb) -> f64 {
+    c.red.max(c.green).max(c.blue) as f64
+}
+
+/// most tentative collisions to follow before giving up on a ray
+const MAX_COLLISIONS: usize = 10000;
+
+#[derive(Clone, Debug)]
+pub struct Medium {
+    /// fraction of light absorbed per unit distance, at a density of 1
+    pub absorption: LinSrgb,
+    /// fraction of light scattered per unit distance, at a density of 1
+    pub scattering: LinSrgb,
+    /// Henyey-Greenstein asymmetry, from -1 (backwards) to 1 (forwards)
+    pub g: f64,
+    pub density: Density,
+}
+
+impl Medium {
+    /// a medium where `albedo` of the light stopped by it is scattered, and
+    /// the rest absorbed
+    pub fn new(extinction: f64, albedo: f64, g: f64, density: Density) -> Medium {
+        let grey = |v: f64| LinSrgb::new(v as f32, v as f32, v as f32);
+        Medium {
+            absorption: grey(extinction * (1. - albedo)),
+            scattering: grey(extinction * albedo),
+            g,
+            density,
+        }
+    }
+
+    fn density_at(&self, p: Point3<f64>) -> f64 {
+        match self.density {
+            Density::Homogeneous => 1.,
+            Density::Grid(ref grid) => grid.density(p),
+        }
+    }
+
+    /// extinction which no point in the medium exceeds, in any channel
+    fn majorant(&self) -> f64 {
+        let max_density = match self.density {
+            Density::Homogeneous => 1.,
+            Density::Grid(ref grid) => grid.max,
+        };
+        max_channel(self.absorption + self.scattering) * max_density
+    }
+
+    /// the part of a ray which can have any density, up to `t_max`
+    fn range(&self, ray: &Ray, t_max: f64) -> Option<(f64, f64)> {
+        match self.density {
+            Density::Homogeneous => Some((0., t_max)),
+            Density::Grid(ref grid) => grid.bounds.span(ray, t_max),
+        }
+    }
+
+    /// follow light backwards along a ray through the medium, up to `t_max`,
+    /// and find where it came from
+    ///
+    /// Returns the filter to apply to light arriving from that event.
+    pub fn track<S: Sampler, P: BackPath>(&self, ray: &Ray, t_max: f64, bpath: &mut P, sampler: &mut S) -> (Event, LinSrgb) {
+        let mut weight = LinSrgb::new(1., 1., 1.);
+        let majorant = self.majorant();
+        let (mut t, end) = match self.range(ray, t_max) {
+            Some(r) if majorant > 0. => r,
+            _ => return (Event::Pass, weight),
+        };
+
+        for _ in 0..MAX_COLLISIONS {
+            t -= (1. - sampler.next()).ln() / majorant;
+            if t >= end { break }
+
+            let density = self.density_at(ray.origin + ray.dir.unwrap() * t);
+            let absorption = self.absorption * density as f32;
+            let scattering = self.scattering * density as f32;
+            let p_absorb = average(absorption) / majorant;
+            let p_scatter = average(scattering) / majorant;
+
+            let u = sampler.next();
+            let bar = majorant as f32;
+            if u < p_absorb {
+                bpath.decide(p_absorb as f32);
+                return (Event::Absorb, weight * absorption / bar)
+            } else if u < p_absorb + p_scatter {
+                bpath.decide(p_scatter as f32);
+                return (Event::Scatter(t), weight * scattering / bar)
+            } else {
+                // null collision, where a colored medium still dims some channels
+                let null = LinSrgb::new(bar, bar, bar) - absorption - scattering;
+                bpath.decide((1. - p_absorb - p_scatter) as f32);
+                weight = weight * null / bar;
+            }
+        }
+        (Event::Pass, weight)
+    }
+
+    /// fraction of light making it through a ray, up to `t_max`, estimated
+    /// by ratio tracking
+    pub fn transmittance<S: Sampler>(&self, ray: &Ray, t_max: f64, sampler: &mut S) -> LinSrgb {
+        let mut tr = LinSrgb::new(1., 1., 1.);
+        let majorant = self.majorant();
+        let (mut t, end) = match self.range(ray, t_max) {
+            Some(r) if majorant > 0. => r,
+            _ => return tr,
+        };
+
+        for _ in 0..MAX_COLLISIONS {
+            t -= (1. - sampler.next()).ln() / majorant;
+            if t >= end || max_channel(tr) < 1e-4 { break }
+
+            let extinction = (self.absorption + self.scattering) * self.density_at(ray.origin + ray.dir.unwrap() * t) as f32;
+            let bar = majorant as f32;
+            tr = tr * (LinSrgb::new(bar, bar, bar) - extinction) / bar;
+        }
+        tr
+    }
+
+    /// pick a direction for light scattered in the medium
+    pub fn scatter(&self, dir: Unit<Vector3<f64>>, sample: (f64, f64)) -> Unit<Vector3<f64>> {
+        henyey_greenstein(dir, self.g, sample)
+    }
+}
@@ -0,0 +1,270 @@
+//! Metropolis light transport in primary sample space, after Kelemen et al.,
+//! "A Simple and Robust Mutation Strategy for the Metropolis Light Transport
+//! Algorithm".
+//!
+//! Every path is made from a list of sample values, as if handed out by any
+//! other sampler. A Markov chain wanders through those lists, mostly by
+//! nudging each value a little and sometimes by starting afresh, and keeps a
+//! new path in proportion to how bright it is compared to the last. Paths
+//! which are hard to find but carry a lot of light, such as light reaching a
+//! room through a gap, are then explored for a while once found.
+//!
+//! Paths can land anywhere on the frame, so everything is splatted. Each tile
+//! runs its own chains, with as many mutations as path samples it would have
+//! taken, and finds its own brightness over the whole frame to normalize
+//! them by. Paths are found by another integrator, such as the path tracer
+//! or the bidirectional path tracer, but all of a bidirectional path's
+//! strategies share one chain rather than each having their own.
+
+use std::f64::consts::PI;
+use imgref::ImgVec;
+use palette::LinSrgb;
+use rand::Rng;
+use rand::prng::XorShiftRng;
+use pipe::{Tile, FrameData, film_point};
+use sample::{World, camera_ray};
+use camera::Ray;
+use sampler::{Sampler, sample_rng, hash};
+use spectral::Channels;
+use integrator::{Integrator, Radiance};
+
+/// one dimension of a sample, and what it was before it was last changed
+#[derive(Copy, Clone, Debug, Default)]
+struct PrimaryValue {
+    value: f64,
+    /// iteration the value was last changed at
+    modified: u64,
+    backup: f64,
+    backup_modified: u64,
+}
+
+/// sample values which are mutated from one path to the next, rather than
+/// picked afresh
+///
+/// Values are only changed when they are used, so paths that stop early
+/// don't pay for dimensions they don't need. A dimension that hasn't been
+/// used for a few iterations takes all the small steps it missed at once.
+pub struct PrimarySample {
+    rng: XorShiftRng,
+    values: Vec<PrimaryValue>,
+    /// dimension handed out next
+    dim: usize,
+    iteration: u64,
+    /// the latest iteration which picked every value afresh and was kept
+    last_large_step: u64,
+    large_step: bool,
+    /// standard deviation of small steps
+    sigma: f64,
+    /// probability of picking every value afresh
+    large_step_prob: f64,
+}
+
+impl PrimarySample {
+    /// start a chain with its own random numbers, where every value is
+    /// picked afresh the first time it is used
+    pub fn new(seed: u64, frame_num: u32, index: usize, sigma: f64, large_step_prob: f64) -> PrimarySample {
+        PrimarySample {
+            rng: sample_rng(seed, frame_num, (index, 0), 0),
+            values: Vec::new(),
+            dim: 0,
+            iteration: 0,
+            last_large_step: 0,
+            large_step: true,
+            sigma,
+            large_step_prob,
+        }
+    }
+
+    /// move on to the next proposed path
+    pub fn mutate(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen_range(0., 1.) < self.large_step_prob;
+    }
+
+    /// keep the values of the proposed path
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    /// go back to the values of the path before the proposed one
+    pub fn reject(&mut self) {
+        for v in &mut self.values {
+            if v.modified == self.iteration {
+                v.value = v.backup;
+                v.modified = v.backup_modified;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    /// a random value in `[0, 1)`, which doesn't change any dimension
+    pub fn uniform(&mut self) -> f64 {
+        self.rng.gen_range(0., 1.)
+    }
+
+    /// bring a dimension up to date with the current iteration
+    fn ensure(&mut self, dim: usize) {
+        if self.values.len() <= dim {
+            self.values.resize(dim + 1, PrimaryValue::default());
+        }
+        let (iteration, last_large_step) = (self.iteration, self.last_large_step);
+        let v = &mut self.values[dim];
+
+        // a large step was kept since this was last used, which would have
+        // picked it afresh
+        if v.modified < last_large_step {
+            v.value = self.rng.gen_range(0., 1.);
+            v.modified = last_large_step;
+        }
+
+        v.backup = v.value;
+        v.backup_modified = v.modified;
+        if self.large_step {
+            v.value = self.rng.gen_range(0., 1.);
+        } else {
+            // a normally distributed step for each iteration since it was last used
+            let steps = (iteration - v.modified) as f64;
+            let (u1, u2): (f64, f64) = (self.rng.gen_range(0., 1.), self.rng.gen_range(0., 1.));
+            let normal = (-2. * (1. - u1).ln()).sqrt() * (2. * PI * u2).cos();
+            v.value += normal * self.sigma * steps.sqrt();
+            v.value -= v.value.floor();
+        }
+        v.modified = iteration;
+    }
+}
+
+impl Sampler for PrimarySample {
+    /// go back to the first dimension, for whichever pixel the values lead to
+    fn start(&mut self, _: (usize, usize), _: usize) {
+        self.dim = 0;
+    }
+
+    fn next(&mut self) -> f64 {
+        let dim = self.dim;
+        self.ensure(dim);
+        self.dim += 1;
+        self.values[dim].value
+    }
+}
+
+/// how bright light looks, which chains keep paths in proportion to
+pub fn luminance(c: LinSrgb) -> f64 {
+    (0.2126 * c.red + 0.7152 * c.green + 0.0722 * c.blue).max(0.) as f64
+}
+
+/// explores paths found by another integrator with Markov chains
+#[derive(Copy, Clone, Debug)]
+pub struct Metropolis<I> {
+    /// finds the light carried by each path
+    pub inner: I,
+    /// paths picked afresh to find the brightness of the frame, and where
+    /// chains start
+    pub bootstrap: usize,
+    /// chains run over the frame, shared out between tiles
+    pub chains: usize,
+    /// standard deviation of small steps
+    pub sigma: f64,
+    /// probability of a large step, which picks a whole new path
+    pub large_step: f64,
+}
+
+impl<I: Integrator> Metropolis<I> {
+    /// the pixel a path from some sample values lands on, and the light it
+    /// carries
+    fn path(&self, frame: &FrameData, size: (usize, usize), sampler: &mut PrimarySample) -> ((usize, usize), LinSrgb) {
+        sampler.start((0, 0), 0);
+        let (u, v) = sampler.next_2d();
+        let (x, y) = (u * size.0 as f64, v * size.1 as f64);
+        let pixel = ((x as usize).min(size.0 - 1), (y as usize).min(size.1 - 1));
+        let light = match camera_ray(&*frame.cam, film_point(size, x, y), sampler, &frame.params) {
+            Some((ray, channels)) => channels.to_rgb(self.inner.radiance(&frame.world, ray, sampler, channels).total()),
+            None => LinSrgb::new(0., 0., 0.),
+        };
+        (pixel, light)
+    }
+}
+
+impl<I: Integrator> Integrator for Metropolis<I> {
+    fn prepare(&self, frame: &mut FrameData, size: (usize, usize), frame_num: u32, threads: usize) {
+        self.inner.prepare(frame, size, frame_num, threads)
+    }
+
+    /// all light is splatted instead
+    fn radiance<S: Sampler>(&self, _: &World, _: Ray, _: &mut S, _: Channels) -> Radiance {
+        Radiance::default()
+    }
+
+    fn splat(&self, tile: &Tile, size: (usize, usize)) -> Option<ImgVec<LinSrgb>> {
+        let frame = &*tile.frame;
+        let share = (tile.buf.width() * tile.buf.height()) as f64 / (size.0 * size.1) as f64;
+        let seed = hash(&[frame.params.seed, tile.left as u64, tile.top as u64]);
+        let chain_sampler = |index| PrimarySample::new(seed, tile.frame_num, index, self.sigma, self.large_step);
+
+        // the average brightness of paths over the whole frame
+        let bootstrap = ((self.bootstrap as f64 * share).round() as usize).max(1);
+        let brightness: Vec<_> = (0..bootstrap)
+            .map(|i| luminance(self.path(frame, size, &mut chain_sampler(i)).1))
+            .collect();
+        let total: f64 = brightness.iter().sum();
+        if total == 0. { return None }
+        let b = total / bootstrap as f64;
+
+        // each of the tile's paths is worth as much as one sample of a pixel
+        let mutations = frame.params.samples * tile.buf.width() * tile.buf.height();
+        let chains = ((self.chains as f64 * share).round() as usize).max(1).min(mutations.max(1));
+        let mut splats = ImgVec::new(vec![LinSrgb::new(0., 0., 0.); size.0 * size.1], size.0, size.1);
+        let mut pick = sample_rng(seed, tile.frame_num, (0, 1), 0);
+        for chain in 0..chains {
+            // start where bootstrap paths were found, in proportion to their brightness
+            let u = pick.gen_range(0., total);
+            let mut sum = 0.;
+            let start = brightness.iter()
+                .position(|&f| { sum += f; u < sum })
+                .unwrap_or(bootstrap - 1);
+            let mut sampler = chain_sampler(start);
+            let (mut pixel, mut light) = self.path(frame, size, &mut sampler);
+            let mut f = luminance(light);
+
+            let count = mutations / chains + if chain < mutations % chains { 1 } else { 0 };
+            for _ in 0..count {
+                sampler.mutate();
+                let (next_pixel, next_light) = self.path(frame, size, &mut sampler);
+                let next_f = luminance(next_light);
+
+                // splat both paths by how likely each is to be kept, which
+                // counts rejected paths too
+                let accept = if f > 0. { (next_f / f).min(1.) } else { 1. };
+                if accept > 0. && next_f > 0. {
+                    splats[next_pixel] = splats[next_pixel] + next_light * (accept / next_f) as f32;
+                }
+                if accept < 1. {
+                    splats[pixel] = splats[pixel] + light * ((1. - accept) / f) as f32;
+                }
+
+                if sampler.uniform() < accept {
+                    pixel = next_pixel;
+                    light = next_light;
+                    f = next_f;
+                    sampler.accept();
+                } else {
+                    sampler.reject();
+                }
+            }
+        }
+
+        // paths are spread over the frame by brightness, which is normalized
+        // out again by how bright the frame is, and by the samples each pixel
+        // would have had
+        let scale = (b / frame.params.samples as f64) as f32;
+        for row in splats.rows_mut() {
+            for px in row.iter_mut() { *px = *px * scale }
+        }
+        Some(splats)
+    }
+
+    fn splats_only(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,109 @@
+//! Movement during a single frame, for motion blur.
+//!
+//! Times are measured in frames, relative to the frame being rendered.
+
+use std::f64::consts::PI;
+use std::str::FromStr;
+use nalg::{Isometry3, Translation3, UnitQuaternion};
+use failure::{Error, format_err};
+use camera::Ray;
+use shape::transform_ray;
+
+/// how much light the shutter lets through as it opens and closes
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ShutterCurve {
+    /// fully open for the whole interval
+    Box,
+    /// opening and closing linearly, fully open only halfway through
+    Triangle,
+    /// opening and closing smoothly, like a raised cosine
+    Smooth,
+}
+
+impl FromStr for ShutterCurve {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ShutterCurve, Error> {
+        match s {
+            "box" => Ok(ShutterCurve::Box),
+            "triangle" => Ok(ShutterCurve::Triangle),
+            "smooth" | "cosine" => Ok(ShutterCurve::Smooth),
+            _ => Err(format_err!("unknown shutter curve \"{}\"", s)),
+        }
+    }
+}
+
+/// the interval during which light reaches the film
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Shutter {
+    pub open: f64,
+    pub close: f64,
+    pub curve: ShutterCurve,
+}
+
+impl Shutter {
+    /// pick a time while the shutter is open, from a uniform sample
+    ///
+    /// Times are picked more often where the curve lets more light through.
+    pub fn sample(&self, u: f64) -> f64 {
+        let x = match self.curve {
+            ShutterCurve::Box => u,
+            ShutterCurve::Triangle => if u < 0.5 {
+                (u / 2.).sqrt()
+            } else {
+                1. - ((1. - u) / 2.).sqrt()
+            },
+            ShutterCurve::Smooth => {
+                // invert x - sin(2πx) / 2π, which has no closed form
+                let mut x = u;
+                for _ in 0..8 {
+                    let cdf = x - (2. * PI * x).sin() / (2. * PI);
+                    let pdf = 1. - (2. * PI * x).cos();
+                    if pdf < 1e-6 { break }
+                    x = (x - (cdf - u) / pdf).max(0.).min(1.);
+                }
+                x
+            },
+        };
+        self.open + x * (self.close - self.open)
+    }
+}
+
+/// a rigid transform changing smoothly between two points in time
+#[derive(Clone, Debug)]
+pub struct Motion {
+    pub start: (f64, Isometry3<f64>),
+    pub end: (f64, Isometry3<f64>),
+}
+
+impl Motion {
+    /// movement by a straight line between two offsets
+    pub fn linear(start: (f64, Translation3<f64>), end: (f64, Translation3<f64>)) -> Motion {
+        Motion {
+            start: (start.0, Isometry3::from_parts(start.1, UnitQuaternion::identity())),
+            end: (end.0, Isometry3::from_parts(end.1, UnitQuaternion::identity())),
+        }
+    }
+
+    /// the transform at a given time, held still outside of the motion
+    pub fn at(&self, time: f64) -> Isometry3<f64> {
+        let (t0, ref a) = self.start;
+        let (t1, ref b) = self.end;
+        if t1 <= t0 { return *a }
+        let s = ((time - t0) / (t1 - t0)).max(0.).min(1.);
+
+        let translation = a.translation.vector * (1. - s) + b.translation.vector * s;
+        let rotation = a.rotation.slerp(&b.rotation, s);
+        Isometry3::from_parts(Translation3::from_vector(translation), rotation)
+    }
+
+    /// move a ray by the transform at the ray's time
+    pub fn apply(&self, ray: Ray) -> Ray {
+        transform_ray(&self.at(ray.time), ray)
+    }
+
+    /// move a ray by the inverse of the transform at the ray's time
+    pub fn unapply(&self, ray: Ray) -> Ray {
+        transform_ray(&self.at(ray.time).inverse(), ray)
+    }
+}
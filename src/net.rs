@@ -0,0 +1,384 @@
+//! Distributed rendering over TCP.
+//!
+//! A coordinator (the normal render command with `--listen`) hands out tiles
+//! to any number of `sidequest worker` processes. When a worker connects it
+//! is sent the coordinator's command line, so it can build exactly the same
+//! scene locally. After that only tile positions and finished pixels need to
+//! cross the network.
+
+use std::io::{self, Read, Write, BufReader, BufWriter};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst}};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use channel::{Receiver, Sender};
+use palette::{Pixel, LinSrgb};
+use failure::{Error, format_err};
+use pipe::{Tile, FrameData};
+use integrator::Integrator;
+use aov::AovSample;
+
+const MAGIC: &[u8; 4] = b"SQ02";
+
+const MSG_JOB: u8 = 1;
+const MSG_TILE: u8 = 2;
+const MSG_FINISH: u8 = 3;
+
+/// how long an idle connection waits between checks for new tiles
+const POLL: Duration = Duration::from_millis(10);
+
+fn write_u32(w: &mut impl Write, v: u32) -> io::Result<()> {
+    w.write_all(&v.to_be_bytes())
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_be_bytes(b))
+}
+
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut b = [0; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn write_f32s(w: &mut impl Write, vs: &[f32]) -> io::Result<()> {
+    for v in vs { write_u32(w, v.to_bits())? }
+    Ok(())
+}
+
+fn read_f32s(r: &mut impl Read, vs: &mut [f32]) -> io::Result<()> {
+    for v in vs { *v = f32::from_bits(read_u32(r)?) }
+    Ok(())
+}
+
+fn read_color(r: &mut impl Read) -> io::Result<LinSrgb> {
+    let mut c = [0.; 3];
+    read_f32s(r, &mut c)?;
+    Ok(LinSrgb::new(c[0], c[1], c[2]))
+}
+
+fn write_pixels(w: &mut impl Write, tile: &Tile) -> io::Result<()> {
+    write_f32s(w, Pixel::into_raw_slice(&tile.buf.buf))?;
+    if let Some(ref aovs) = tile.aovs {
+        for a in aovs.buf.iter() {
+            write_f32s(w, &[a.depth])?;
+            write_f32s(w, &a.normal)?;
+            write_u32(w, a.id)?;
+            for &c in &[a.albedo, a.direct, a.indirect, a.emission] {
+                write_f32s(w, &[c.red, c.green, c.blue])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_pixels(r: &mut impl Read, tile: &mut Tile) -> io::Result<()> {
+    read_f32s(r, Pixel::into_raw_slice_mut(&mut tile.buf.buf))?;
+    if let Some(ref mut aovs) = tile.aovs {
+        for a in aovs.buf.iter_mut() {
+            let mut depth = [0.];
+            read_f32s(r, &mut depth)?;
+            let mut normal = [0.; 3];
+            read_f32s(r, &mut normal)?;
+            *a = AovSample {
+                depth: depth[0],
+                normal,
+                id: read_u32(r)?,
+                albedo: read_color(r)?,
+                direct: read_color(r)?,
+                indirect: read_color(r)?,
+                emission: read_color(r)?,
+            };
+        }
+    }
+    Ok(())
+}
+
+/// the location of a tile within a frame
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Job {
+    frame_num: u32,
+    left: u32,
+    top: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Job {
+    fn of(tile: &Tile) -> Job {
+        Job {
+            frame_num: tile.frame_num,
+            left: tile.left as u32,
+            top: tile.top as u32,
+            width: tile.buf.width() as u32,
+            height: tile.buf.height() as u32,
+        }
+    }
+
+    fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        for &v in &[self.frame_num, self.left, self.top, self.width, self.height] {
+            write_u32(w, v)?;
+        }
+        Ok(())
+    }
+
+    fn read(r: &mut impl Read) -> io::Result<Job> {
+        Ok(Job {
+            frame_num: read_u32(r)?,
+            left: read_u32(r)?,
+            top: read_u32(r)?,
+            width: read_u32(r)?,
+            height: read_u32(r)?,
+        })
+    }
+}
+
+/// listens for workers and feeds them tiles from the render pipeline
+pub struct Coordinator {
+    listener: TcpListener,
+    args: Vec<String>,
+}
+
+impl Coordinator {
+    /// listen on `addr`, sending `args` to every worker that connects
+    pub fn bind(addr: impl ToSocketAddrs, args: Vec<String>) -> Result<Coordinator, Error> {
+        let listener = TcpListener::bind(addr)?;
+
+        // poll for workers, so that shutting down doesn't have to wait for
+        // one more to connect
+        listener.set_nonblocking(true)?;
+        Ok(Coordinator { listener, args })
+    }
+
+    /// accept workers in the background until `running` is cleared
+    ///
+    /// Tiles of `size` frames are taken from `retry` and `input`, and finished
+    /// tiles are sent to `output`. If a worker disconnects, its tile is put
+    /// into `retry`. `connected` counts the workers currently connected. The
+    /// returned thread finishes once every worker has been told there is no
+    /// more work.
+    pub fn start(
+        self,
+        size: (usize, usize),
+        input: Receiver<Tile>,
+        output: Sender<Tile>,
+        retry: (Sender<Tile>, Receiver<Tile>),
+        running: Arc<AtomicBool>,
+        connected: Arc<AtomicUsize>,
+    ) -> JoinHandle<()> {
+        let Coordinator { listener, args } = self;
+        let args = Arc::new(args);
+
+        thread::spawn(move || {
+            let mut served = Vec::new();
+            while running.load(SeqCst) {
+                let stream = match listener.accept() {
+                    Ok((s, _)) => s,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => { thread::sleep(POLL); continue },
+                    Err(e) => { eprintln!("could not accept worker: {}", e); continue },
+                };
+                let (input, output, retry, running, connected, args) =
+                    (input.clone(), output.clone(), retry.clone(), running.clone(), connected.clone(), args.clone());
+                served.push(thread::spawn(move || {
+                    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+                    connected.fetch_add(1, SeqCst);
+                    if let Err(e) = serve(stream, size, &args, &input, &output, &retry, &running) {
+                        eprintln!("lost worker {}: {}", peer, e);
+                    }
+                    connected.fetch_sub(1, SeqCst);
+                }));
+            }
+
+            for handle in served {
+                if handle.join().is_err() { eprintln!("worker connection panicked") }
+            }
+        })
+    }
+}
+
+/// hand tiles to a single worker connection until shutdown or failure
+fn serve(
+    stream: TcpStream,
+    size: (usize, usize),
+    args: &[String],
+    input: &Receiver<Tile>,
+    output: &Sender<Tile>,
+    (retry_send, retry): &(Sender<Tile>, Receiver<Tile>),
+    running: &AtomicBool,
+) -> Result<(), Error> {
+    // accepted from a listener which doesn't block
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    let mut r = BufReader::new(stream.try_clone()?);
+    let mut w = BufWriter::new(stream);
+
+    w.write_all(MAGIC)?;
+    write_u32(&mut w, size.0 as u32)?;
+    write_u32(&mut w, size.1 as u32)?;
+    write_u32(&mut w, args.len() as u32)?;
+    for arg in args {
+        write_u32(&mut w, arg.len() as u32)?;
+        w.write_all(arg.as_bytes())?;
+    }
+    w.flush()?;
+
+    loop {
+        if !running.load(SeqCst) {
+            w.write_all(&[MSG_FINISH])?;
+            w.flush()?;
+            return Ok(())
+        }
+
+        let mut tile = match retry.try_recv().or_else(|| input.try_recv()) {
+            Some(t) => t,
+            None => { thread::sleep(POLL); continue },
+        };
+
+        match exchange(&mut r, &mut w, &mut tile) {
+            Ok(()) => output.send(tile),
+            Err(e) => {
+                // give the tile to someone else
+                retry_send.send(tile);
+                return Err(e)
+            },
+        }
+    }
+}
+
+/// send a single job and wait for the rendered pixels
+fn exchange(r: &mut impl Read, w: &mut impl Write, tile: &mut Tile) -> Result<(), Error> {
+    let job = Job::of(tile);
+    w.write_all(&[MSG_JOB])?;
+    job.write(w)?;
+    w.flush()?;
+
+    if read_u8(r)? != MSG_TILE { return Err(format_err!("worker sent unexpected message")) }
+    if Job::read(r)? != job { return Err(format_err!("worker returned the wrong tile")) }
+    read_pixels(r, tile)?;
+    Ok(())
+}
+
+/// connect to a coordinator and render tiles for it
+///
+/// One connection is opened per thread. `setup` is given the coordinator's
+/// command line and must return a function creating the data for each frame,
+/// and the integrator to render it with.
+/// Lost connections are retried until none succeed for `patience`.
+pub fn run_worker<S, F, I>(addr: &str, threads: usize, patience: Duration, setup: S) -> Result<(), Error>
+    where S: Fn(&[String]) -> Result<(F, I), Error> + Sync + Send + 'static,
+          F: FnMut(u32) -> Result<Option<FrameData>, Error>,
+          I: Integrator,
+{
+    let setup = Arc::new(setup);
+    let prepared = Arc::new(Mutex::new(None));
+    let handles: Vec<_> = (0..threads).map(|_| {
+        let addr = addr.to_string();
+        let setup = setup.clone();
+        let prepared = prepared.clone();
+        thread::spawn(move || -> Result<(), Error> {
+            let mut last_ok = Instant::now();
+            loop {
+                match work(&addr, &*setup, &mut last_ok, threads, &prepared) {
+                    Ok(()) => return Ok(()),
+                    Err(e) => {
+                        if last_ok.elapsed() > patience {
+                            return Err(format_err!("giving up on coordinator {}: {}", addr, e))
+                        }
+                        eprintln!("connection to {} failed ({}), retrying", addr, e);
+                        thread::sleep(Duration::from_secs(1));
+                    },
+                }
+            }
+        })
+    }).collect();
+
+    for handle in handles {
+        handle.join().map_err(|_| format_err!("worker thread panicked"))??;
+    }
+    Ok(())
+}
+
+/// serve a single connection to the coordinator, until it has no more work
+///
+/// `last_ok` is updated whenever the coordinator is heard from. The latest
+/// frame is shared between connections in `prepared`, so that it is only
+/// prepared once, with all `threads`.
+fn work<S, F, I>(
+    addr: &str,
+    setup: &S,
+    last_ok: &mut Instant,
+    threads: usize,
+    prepared: &Mutex<Option<(u32, Arc<FrameData>)>>,
+) -> Result<(), Error>
+    where S: Fn(&[String]) -> Result<(F, I), Error>,
+          F: FnMut(u32) -> Result<Option<FrameData>, Error>,
+          I: Integrator,
+{
+    let stream = TcpStream::connect(addr)?;
+    stream.set_nodelay(true)?;
+    let mut r = BufReader::new(stream.try_clone()?);
+    let mut w = BufWriter::new(stream);
+
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC { return Err(format_err!("{} is not a sidequest coordinator", addr)) }
+    let size = (read_u32(&mut r)? as usize, read_u32(&mut r)? as usize);
+    let mut args = Vec::new();
+    for _ in 0..read_u32(&mut r)? {
+        let mut arg = vec![0; read_u32(&mut r)? as usize];
+        r.read_exact(&mut arg)?;
+        args.push(String::from_utf8(arg)?);
+    }
+    let (mut frames, integrator) = setup(&args)?;
+    *last_ok = Instant::now();
+
+    let mut current: Option<(u32, Arc<FrameData>)> = None;
+    loop {
+        match read_u8(&mut r)? {
+            MSG_JOB => (),
+            MSG_FINISH => return Ok(()),
+            _ => return Err(format_err!("coordinator sent unexpected message")),
+        }
+        let job = Job::read(&mut r)?;
+        *last_ok = Instant::now();
+
+        // consecutive tiles are almost always from the same frame, which
+        // whichever connection needs it first prepares for the others
+        let frame = match current {
+            Some((n, ref f)) if n == job.frame_num => f.clone(),
+            _ => {
+                let mut shared = prepared.lock().unwrap();
+                let f = match *shared {
+                    Some((n, ref f)) if n == job.frame_num => f.clone(),
+                    _ => {
+                        let mut f = frames(job.frame_num)?
+                            .ok_or_else(|| format_err!("frame {} does not exist", job.frame_num))?;
+                        integrator.prepare(&mut f, size, job.frame_num, threads);
+                        let f = Arc::new(f);
+                        *shared = Some((job.frame_num, f.clone()));
+                        f
+                    },
+                };
+                current = Some((job.frame_num, f.clone()));
+                f
+            },
+        };
+
+        let mut tile = Tile::new(
+            job.frame_num,
+            job.left as usize,
+            job.top as usize,
+            job.width as usize,
+            job.height as usize,
+            frame,
+        );
+        tile.render(size, &integrator);
+
+        w.write_all(&[MSG_TILE])?;
+        job.write(&mut w)?;
+        write_pixels(&mut w, &tile)?;
+        w.flush()?;
+    }
+}
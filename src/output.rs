@@ -0,0 +1,123 @@
+//! Saving finished frames to disk.
+
+use image;
+use palette::{LinSrgb, Srgba, Alpha, Pixel};
+use failure::Error;
+use pipe::FullFrame;
+use aov::{Aov, AovSample};
+use exr::{self, Channel};
+
+/// convert linear light to displayable 8-bit color
+pub fn to_srgba(color: LinSrgb) -> Srgba<u8> {
+    Srgba::from_linear(Alpha { color, alpha: 1. }).into_format()
+}
+
+fn is_exr(path: &str) -> bool {
+    path.to_lowercase().ends_with(".exr")
+}
+
+/// insert a suffix before the extension of a path
+pub fn suffixed_path(path: &str, suffix: &str) -> String {
+    let ext = path.rfind('.')
+        .filter(|&i| !path[i..].contains('/'))
+        .unwrap_or(path.len());
+    format!("{}.{}{}", &path[..ext], suffix, &path[ext..])
+}
+
+/// path of a separate AOV file, with the pass name inserted before the extension
+pub fn aov_path(path: &str, aov: Aov) -> String {
+    suffixed_path(path, aov.name())
+}
+
+fn save_srgba(path: &str, width: usize, height: usize, pixels: Vec<Srgba<u8>>) -> Result<(), Error> {
+    image::save_buffer(
+        path,
+        Pixel::into_raw_slice(&pixels),
+        width as u32,
+        height as u32,
+        image::ColorType::RGBA(8),
+    )?;
+    Ok(())
+}
+
+/// split a pass into EXR channels, optionally prefixed by a layer name
+fn channels(names: &[&str], layer: Option<&str>, values: &[[f32; 3]]) -> Vec<Channel> {
+    names.iter().enumerate().map(|(c, name)| Channel::new(
+        match layer {
+            Some(layer) => format!("{}.{}", layer, name),
+            None => name.to_string(),
+        },
+        values.iter().map(|v| v[c]).collect(),
+    )).collect()
+}
+
+/// show a pass as an 8-bit image, for formats which can't hold raw values
+///
+/// Depth is scaled so that the farthest surface is white, normals are mapped
+/// from `[-1, 1]` to `[0, 1]`, and each object ID gets an arbitrary color.
+fn aov_pixel(aov: Aov, s: &AovSample, max_depth: f32) -> Srgba<u8> {
+    let raw = |v: [f32; 3]| {
+        let q = |x: f32| (x.max(0.).min(1.) * 255.).round() as u8;
+        Srgba::new(q(v[0]), q(v[1]), q(v[2]), 255)
+    };
+
+    match aov {
+        Aov::Depth => {
+            let d = if max_depth > 0. { s.depth / max_depth } else { 0. };
+            raw([d, d, d])
+        },
+        Aov::Normal => raw([
+            s.normal[0] * 0.5 + 0.5,
+            s.normal[1] * 0.5 + 0.5,
+            s.normal[2] * 0.5 + 0.5,
+        ]),
+        Aov::ObjectId if s.id == 0 => raw([0., 0., 0.]),
+        Aov::ObjectId => {
+            let h = s.id.wrapping_mul(0x9e3779b9);
+            Srgba::new((h >> 24) as u8 | 0x40, (h >> 16) as u8 | 0x40, (h >> 8) as u8 | 0x40, 255)
+        },
+        _ => {
+            let v = aov.values(s);
+            to_srgba(LinSrgb::new(v[0], v[1], v[2]))
+        },
+    }
+}
+
+/// save a finished frame, along with the given passes
+///
+/// EXR files hold the passes as extra layers, unless `separate` is set. Any
+/// other format gets a file for each pass, named by `aov_path`.
+pub fn save(path: &str, frame: &FullFrame, aovs: &[Aov], separate: bool) -> Result<(), Error> {
+    let (w, h) = (frame.buf.width(), frame.buf.height());
+    let samples = match frame.aovs {
+        Some(ref a) => &a.buf[..],
+        None => &[],
+    };
+    let aovs = if samples.is_empty() { &[] } else { aovs };
+
+    if is_exr(path) {
+        let beauty: Vec<_> = frame.buf.buf.iter().map(|c| [c.red, c.green, c.blue]).collect();
+        let mut layers = channels(&["R", "G", "B"], None, &beauty);
+        for &aov in aovs {
+            let values: Vec<_> = samples.iter().map(|s| aov.values(s)).collect();
+            if separate {
+                exr::write(aov_path(path, aov), w, h, channels(aov.channels(), None, &values))?;
+            } else {
+                layers.extend(channels(aov.channels(), Some(aov.name()), &values));
+            }
+        }
+        exr::write(path, w, h, layers)
+    } else {
+        save_srgba(path, w, h, frame.buf.buf.iter().map(|&c| to_srgba(c)).collect())?;
+        let max_depth = samples.iter().map(|s| s.depth).fold(0., f32::max);
+        for &aov in aovs {
+            save_srgba(
+                &aov_path(path, aov),
+                w,
+                h,
+                samples.iter().map(|s| aov_pixel(aov, s, max_depth)).collect(),
+            )?;
+        }
+        Ok(())
+    }
+}
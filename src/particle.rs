@@ -0,0 +1,116 @@
+//! Light tracing, where paths start from emitters and every point they reach
+//! is joined straight to the camera, to be splatted wherever it lands on the
+//! film.
+//!
+//! In a scene lit only by emitting spheres, this finds the same light as the
+//! path tracer does from the other end, so the two can be rendered side by
+//! side to check each other, and the camera's importance along with them.
+//! Light only starts from emitters that can be picked (see `lights`), so the
+//! sky and other emitters are never seen. The camera has to be able to say
+//! where light lands on its film, which for now is only a pinhole
+//! perspective camera. Like the bidirectional path tracer, media and
+//! subsurface scattering are ignored.
+
+use std::f64::consts::PI;
+use imgref::ImgVec;
+use nalg::{Vector3, Unit};
+use palette::LinSrgb;
+use camera::Ray;
+use pipe::{Tile, film_pixel};
+use sample::{World, reflect};
+use sampler::{Sampler, Independent, hash};
+use spectral::Channels;
+use integrator::{Integrator, Radiance, facing, diffuse_dir};
+
+/// follows light forwards from emitters to the camera
+#[derive(Copy, Clone, Debug)]
+pub struct LightTracer {
+    /// most times light may bounce
+    pub bounce_limit: usize,
+}
+
+impl Integrator for LightTracer {
+    /// all light is splatted instead
+    fn radiance<S: Sampler>(&self, _: &World, _: Ray, _: &mut S, _: Channels) -> Radiance {
+        Radiance::default()
+    }
+
+    fn splat(&self, tile: &Tile, size: (usize, usize)) -> Option<ImgVec<LinSrgb>> {
+        let frame = &*tile.frame;
+        let world = &frame.world;
+        let params = &frame.params;
+        let mut splats = ImgVec::new(vec![LinSrgb::new(0., 0., 0.); size.0 * size.1], size.0, size.1);
+
+        // light leaving a point on the side a normal faces, towards the
+        // camera if it can be seen
+        let mut to_camera = |point, norm: Unit<Vector3<f64>>, time, light: LinSrgb, channels: Channels| {
+            let seen = match frame.cam.project(point) {
+                Some(seen) => seen,
+                None => return,
+            };
+            let cos = norm.dot(&(seen.origin - point).normalize());
+            if cos <= 0. { return }
+            if let Some(pixel) = film_pixel(size, seen.film) {
+                if world.visible(point, seen.origin, time) {
+                    splats[pixel] = splats[pixel] + channels.to_rgb(light * (cos * seen.importance) as f32);
+                }
+            }
+        };
+
+        // each of the tile's paths is worth as much as one sample of a pixel
+        let paths = params.samples * tile.buf.width() * tile.buf.height();
+        let mut sampler = Independent::new(hash(&[params.seed, tile.left as u64, tile.top as u64]), tile.frame_num);
+        for i in 0..paths {
+            sampler.start((i, 0), 0);
+            let time = params.shutter.sample(sampler.next());
+            let channels = if params.spectral { Channels::sample(sampler.next()) } else { Channels::Rgb };
+            let (light, dir, pdf) = match world.lights().sample(&world.objects, time, &mut sampler) {
+                Some(l) => l,
+                None => return None,
+            };
+            let emission = channels.radiance(world.objects[light.object].material.emission);
+
+            // the emitter itself, which only shines outwards
+            to_camera(light.point, light.norm, time, emission / light.pdf as f32, channels);
+
+            // emission is cosine weighted, so only a factor of pi is left
+            let beta = emission * (dir.dot(&light.norm) / (pdf * light.pdf)) as f32;
+            let mut ray = Ray::new(light.point, dir).at_time(time);
+            for _ in 0..self.bounce_limit {
+                let hit = match world.hit_surface(ray) {
+                    Some(hit) => hit,
+                    None => break,
+                };
+                let (_, o) = hit.data;
+                let point = ray.origin + hit.t * ray.dir.unwrap();
+                let refl = o.material.reflectivity as f64;
+                let norm = facing(&ray, hit.norm);
+
+                // diffuse reflection towards the camera, which can't pass
+                // through the surface
+                if refl < 1. {
+                    to_camera(point, norm, time, beta * ((1. - refl) / PI) as f32, channels);
+                }
+
+                // the probability of either kind of reflection cancels out its filter
+                let dir = if sampler.next() < refl {
+                    reflect(ray.dir, hit.norm)
+                } else {
+                    diffuse_dir(norm, sampler.next_2d())
+                };
+                ray = Ray::new(point, dir).at_time(time);
+            }
+        }
+
+        // each pixel would have had as many samples
+        let scale = 1. / params.samples as f32;
+        for row in splats.rows_mut() {
+            for px in row.iter_mut() { *px = *px * scale }
+        }
+        Some(splats)
+    }
+
+    fn splats_only(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,317 @@
+//! Photon mapping, where light is followed forwards from emitters and left
+//! as photons on the surfaces it reaches, to be gathered up again from the
+//! camera's side. Caustics, where light is focused onto a surface by
+//! mirrors or glass, are found this way far sooner than by path tracing.
+//!
+//! Rendering is progressive in the sense of Knaus and Zwicker's
+//! "Progressive Photon Mapping: A Probabilistic Approach": each frame traces
+//! several independent passes of photons, gathered over a radius which
+//! shrinks from pass to pass, and each camera sample uses one of the passes.
+//! The blur of gathering fades as more passes are averaged.
+//!
+//! Photons are traced once for each frame, at the start of the frame, from
+//! emitters that can be picked (see `lights`). Light from the sky and other
+//! emitters is still found by path tracing. Photons carry RGB, so there is no
+//! dispersion in caustics, and they pass through fog without stopping.
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+use std::thread;
+use nalg::{Point3, Vector3, Unit};
+use palette::LinSrgb;
+use camera::{Ray, Impact};
+use bvh::Aabb;
+use sample::{World, Object, reflect};
+use subsurface::Subsurface;
+use sampler::{Sampler, Independent, hash};
+use spectral::Channels;
+use stats::{BackPath, ForPath, MulBackPath};
+use integrator::{Integrator, Radiance, facing, diffuse_dir};
+use pipe::FrameData;
+
+/// light left on a surface
+#[derive(Copy, Clone, Debug)]
+pub struct Photon {
+    pub point: Point3<f64>,
+    /// direction back towards where the light came from
+    pub from: Unit<Vector3<f64>>,
+    pub power: LinSrgb,
+}
+
+/// photons arranged so that those near a point can be found quickly
+///
+/// The tree is kept in a single list: the middle of each range of photons is
+/// the node splitting it, with the photons on either side of it before and
+/// after.
+#[derive(Clone, Debug)]
+pub struct KdTree {
+    photons: Vec<Photon>,
+    /// axis each node splits along
+    axes: Vec<u8>,
+}
+
+impl KdTree {
+    pub fn new(mut photons: Vec<Photon>) -> KdTree {
+        let mut axes = vec![0; photons.len()];
+        KdTree::build(&mut photons, &mut axes);
+        KdTree { photons, axes }
+    }
+
+    fn build(photons: &mut [Photon], axes: &mut [u8]) {
+        use std::cmp::Ordering;
+
+        if photons.len() <= 1 { return }
+
+        // split along the axis the photons are most spread out on
+        let spread = photons.iter()
+            .fold(Aabb::empty(), |b, p| b.union(&Aabb { min: p.point, max: p.point }));
+        let size = spread.max - spread.min;
+        let axis = if size.x >= size.y && size.x >= size.z { 0 } else if size.y >= size.z { 1 } else { 2 };
+        photons.sort_unstable_by(|a, b| a.point[axis].partial_cmp(&b.point[axis]).unwrap_or(Ordering::Equal));
+
+        let mid = photons.len() / 2;
+        axes[mid] = axis as u8;
+        let (photons_before, photons_after) = photons.split_at_mut(mid);
+        let (axes_before, axes_after) = axes.split_at_mut(mid);
+        KdTree::build(photons_before, axes_before);
+        KdTree::build(&mut photons_after[1..], &mut axes_after[1..]);
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// call `f` with every photon within `radius` of a point
+    pub fn within<F: FnMut(&Photon)>(&self, point: Point3<f64>, radius: f64, mut f: F) {
+        self.search(0, self.photons.len(), point, radius * radius, &mut f);
+    }
+
+    fn search<F: FnMut(&Photon)>(&self, start: usize, end: usize, point: Point3<f64>, r2: f64, f: &mut F) {
+        if start >= end { return }
+        let mid = start + (end - start) / 2;
+        let photon = &self.photons[mid];
+        if (photon.point - point).norm_squared() <= r2 { f(photon) }
+
+        // the side the point is on, then the other side if the radius reaches it
+        let axis = self.axes[mid] as usize;
+        let d = point[axis] - photon.point[axis];
+        let (near, far) = if d <= 0. { ((start, mid), (mid + 1, end)) } else { ((mid + 1, end), (start, mid)) };
+        self.search(near.0, near.1, point, r2, f);
+        if d * d <= r2 { self.search(far.0, far.1, point, r2, f) }
+    }
+}
+
+/// one pass of photons, and the radius they are gathered over
+#[derive(Clone, Debug)]
+pub struct PhotonMap {
+    pub tree: KdTree,
+    pub radius: f64,
+}
+
+impl PhotonMap {
+    /// light reflected by a diffuse surface at a point, before the surface's
+    /// reflectivity is applied
+    fn gather(&self, point: Point3<f64>, norm: Unit<Vector3<f64>>) -> LinSrgb {
+        let mut power = LinSrgb::new(0., 0., 0.);
+        self.tree.within(point, self.radius, |p| {
+            // light is only reflected, so must have arrived on the same side
+            if p.from.dot(&norm) > 0. { power = power + p.power }
+        });
+
+        // spread over the disc gathered from, and reflected in all directions
+        power / (PI * self.radius * self.radius * PI) as f32
+    }
+}
+
+/// every pass of photons traced for a frame
+#[derive(Clone, Debug, Default)]
+pub struct PhotonMaps {
+    pub passes: Vec<PhotonMap>,
+}
+
+/// carry light through the surface of a subsurface material, either way
+///
+/// Returns where the light leaves and the filter to apply, or `None` if it
+/// was absorbed inside.
+pub fn through<S: Sampler>(world: &World, ray: Ray, hit: &Impact<(usize, &Object)>, sub: &Subsurface, sampler: &mut S, channels: Channels) -> Option<(Ray, LinSrgb)> {
+    let (n, o) = hit.data;
+    let surface = Impact { t: hit.t, norm: hit.norm, data: o };
+    let mut bpath = MulBackPath::new();
+    let (out, weight) = world.subsurface(ray, &surface, n, sub, &mut bpath, sampler, channels)?;
+    Some((out.at_time(ray.time), bpath.source(weight).lum()))
+}
+
+/// gathers photons where light first reflects diffusely, or only caustics
+/// from them at every diffuse reflection of a path tracer
+#[derive(Copy, Clone, Debug)]
+pub struct PhotonMapping {
+    /// most times light may bounce
+    pub bounce_limit: usize,
+    /// photons traced in each pass
+    pub photons: usize,
+    pub passes: usize,
+    /// radius photons are gathered over in the first pass
+    pub radius: f64,
+    /// fraction of photons kept from one pass to the next, which sets how
+    /// quickly the radius shrinks
+    pub alpha: f64,
+    /// only keep photons which reached a surface by specular reflection or
+    /// through glass, and find everything else by path tracing
+    pub caustics: bool,
+    pub seed: u64,
+}
+
+impl PhotonMapping {
+    /// follow photons from the emitters, and keep those landing on surfaces
+    /// which reflect diffusely
+    fn trace_pass(&self, world: &World, frame_num: u32, pass: usize) -> Vec<Photon> {
+        // photons shouldn't use the same random numbers as camera paths
+        let mut sampler = Independent::new(hash(&[self.seed, pass as u64]), frame_num);
+        let mut photons = Vec::new();
+
+        for i in 0..self.photons {
+            sampler.start((i, pass), 0);
+            let (light, dir, pdf) = match world.lights().sample(&world.objects, 0., &mut sampler) {
+                Some(l) => l,
+                None => break,
+            };
+            let emission = world.objects[light.object].material.emission;
+            let mut power = emission * (dir.dot(&light.norm) / (pdf * light.pdf * self.photons as f64)) as f32;
+
+            // whether the photon has only been reflected specularly so far
+            let mut specular = true;
+            let mut ray = Ray::new(light.point, dir);
+            for bounce in 0..self.bounce_limit {
+                let hit = match world.hit_surface(ray) {
+                    Some(hit) => hit,
+                    None => break,
+                };
+                let (_, o) = hit.data;
+                let point = ray.origin + hit.t * ray.dir.unwrap();
+                let refl = o.material.reflectivity as f64;
+                let diffuse = refl < 1. && o.material.subsurface.is_none();
+                if diffuse && (!self.caustics || specular && bounce > 0) {
+                    photons.push(Photon { point, from: -ray.dir, power });
+                }
+
+                // the probability of each kind of reflection cancels out its filter
+                ray = if sampler.next() < refl {
+                    Ray::new(point, reflect(ray.dir, hit.norm))
+                } else if let Some(ref sub) = o.material.subsurface {
+                    specular &= sub.clear();
+                    if self.caustics && !specular { break }
+                    match through(world, ray, &hit, sub, &mut sampler, Channels::Rgb) {
+                        Some((out, weight)) => {
+                            power = power * weight;
+                            out
+                        },
+                        None => break,
+                    }
+                } else {
+                    if self.caustics { break }
+                    specular = false;
+                    Ray::new(point, diffuse_dir(facing(&ray, hit.norm), sampler.next_2d()))
+                };
+            }
+        }
+        photons
+    }
+
+    /// light reflected by a diffuse surface, found from one of the passes
+    fn gather<S: Sampler>(&self, world: &World, point: Point3<f64>, norm: Unit<Vector3<f64>>, sampler: &mut S, channels: Channels) -> LinSrgb {
+        let maps = match world.photons {
+            Some(ref maps) if !maps.passes.is_empty() => maps,
+            _ => return LinSrgb::new(0., 0., 0.),
+        };
+        let pass = ((sampler.next() * maps.passes.len() as f64) as usize).min(maps.passes.len() - 1);
+        channels.radiance(maps.passes[pass].gather(point, norm))
+    }
+}
+
+impl Integrator for PhotonMapping {
+    fn prepare(&self, frame: &mut FrameData, _: (usize, usize), frame_num: u32, threads: usize) {
+        // passes don't depend on each other, so they are shared out between threads
+        let workers = threads.max(1).min(self.passes.max(1));
+        let world = &frame.world;
+        let mut trees: Vec<(usize, KdTree)> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers).map(|t| scope.spawn(move || {
+                (t..self.passes).step_by(workers)
+                    .map(|pass| (pass, KdTree::new(self.trace_pass(world, frame_num, pass))))
+                    .collect::<Vec<_>>()
+            })).collect();
+            handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+        });
+        trees.sort_by_key(|&(pass, _)| pass);
+
+        let mut radius = self.radius;
+        let mut passes = Vec::with_capacity(self.passes);
+        for (pass, tree) in trees {
+            passes.push(PhotonMap { tree, radius });
+
+            // keep alpha of the photons in the next pass's disc, so
+            // r²(i + 1) = r²(i) (i + alpha) / (i + 1), counting from 1
+            let i = (pass + 1) as f64;
+            radius *= ((i + self.alpha) / (i + 1.)).sqrt();
+        }
+        frame.world.photons = Some(Arc::new(PhotonMaps { passes }));
+    }
+
+    fn radiance<S: Sampler>(&self, world: &World, mut ray: Ray, sampler: &mut S, channels: Channels) -> Radiance {
+        let mut light = Radiance::default();
+        let mut beta = LinSrgb::new(1., 1., 1.);
+
+        // light from picked emitters is already in the photons once they
+        // have been gathered, or for caustics, once they have been gathered
+        // and only specular reflections followed since
+        let mut gathered = false;
+        let mut specular_since = false;
+        for bounce in 0..=self.bounce_limit {
+            let hit = match world.hit_surface(ray) {
+                Some(hit) => hit,
+                None => {
+                    light.add(bounce, beta * channels.radiance(world.ambient));
+                    break
+                },
+            };
+            let (n, o) = hit.data;
+            let point = ray.origin + hit.t * ray.dir.unwrap();
+            let photons = gathered && (specular_since || !self.caustics);
+            if !photons || world.lights().pdf(&world.objects, n) == 0. {
+                light.add(bounce, beta * channels.radiance(o.material.emission));
+            }
+            if bounce == self.bounce_limit { break }
+
+            ray = if sampler.next() < o.material.reflectivity as f64 {
+                specular_since = true;
+                Ray::new(point, reflect(ray.dir, hit.norm)).at_time(ray.time)
+            } else if let Some(ref sub) = o.material.subsurface {
+                if sub.clear() {
+                    specular_since = true;
+                } else if self.caustics {
+                    // light scattered inside never left caustic photons
+                    gathered = false;
+                }
+                match through(world, ray, &hit, sub, sampler, channels) {
+                    Some((out, weight)) => {
+                        beta = beta * weight;
+                        out
+                    },
+                    None => break,
+                }
+            } else {
+                let norm = facing(&ray, hit.norm);
+                if self.caustics || !gathered {
+                    light.add(bounce + 1, beta * self.gather(world, point, norm, sampler, channels));
+                }
+                gathered = true;
+                specular_since = false;
+                Ray::new(point, diffuse_dir(norm, sampler.next_2d())).at_time(ray.time)
+            };
+        }
+        light
+    }
+}
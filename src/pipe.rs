@@ -1,71 +1,190 @@
 use imgref::{ImgVec};
 use nalg::Point2;
-use camera::DefocusCamera;
+use camera::{Camera, CameraSample};
 use sample::{World, SampleParams};
 use dynpool::{System, Pool, Scale, Decision};
-use channel::{Receiver, Sender};
-use std::sync::{Arc, atomic::{AtomicBool, Ordering::SeqCst}};
-use palette::{Srgba, Alpha};
-use rand::{self, ThreadRng};
-use failure::Error;
+use channel::{Receiver, Sender, Select, after};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst}};
+use std::time::Duration;
+use palette::LinSrgb;
+use failure::{Error, format_err};
+use aov::AovSample;
+use net::Coordinator;
+use sampler::Sampler;
+use integrator::Integrator;
 
 pub struct FrameData {
     pub world: World,
-    pub cam: DefocusCamera,
+    pub cam: Box<dyn Camera<CameraSample> + Send + Sync>,
     pub params: SampleParams,
+    /// light found by tiles landing anywhere on the frame
+    pub splats: Splats,
+}
+
+/// light which can land anywhere on a frame, added up from every tile
+#[derive(Default)]
+pub struct Splats {
+    buf: Mutex<Option<ImgVec<LinSrgb>>>,
+}
+
+impl Splats {
+    /// add light from a buffer the size of the frame
+    pub fn add(&self, splats: ImgVec<LinSrgb>) {
+        let mut buf = self.buf.lock().unwrap();
+        match *buf {
+            Some(ref mut buf) => add_buf(buf, &splats),
+            None => *buf = Some(splats),
+        }
+    }
+
+    /// take all the light added so far
+    pub fn take(&self) -> Option<ImgVec<LinSrgb>> {
+        self.buf.lock().unwrap().take()
+    }
+}
+
+/// point on the film at a position in pixels from the top left of the frame
+///
+/// The film spans [-1, 1] along the longer side, and is centered along the
+/// shorter.
+pub fn film_point(size: (usize, usize), x: f64, y: f64) -> Point2<f64> {
+    let pixel_width = 1. / size.1.max(size.0) as f64;
+    Point2::new(
+        (x - size.0 as f64 / 2.) * pixel_width * 2.,
+        (y - size.1 as f64 / 2.) * pixel_width * 2.,
+    )
+}
+
+/// pixel a point on the film lands in, if it is on the frame
+pub fn film_pixel(size: (usize, usize), film: Point2<f64>) -> Option<(usize, usize)> {
+    let pixel_width = 1. / size.1.max(size.0) as f64;
+    let x = film.x / (pixel_width * 2.) + size.0 as f64 / 2.;
+    let y = film.y / (pixel_width * 2.) + size.1 as f64 / 2.;
+    if x < 0. || y < 0. || x >= size.0 as f64 || y >= size.1 as f64 { return None }
+    Some((x as usize, y as usize))
 }
 
 pub struct Tile {
     pub frame_num: u32,
     pub top: usize,
     pub left: usize,
-    pub buf: ImgVec<Srgba<u8>>,
+    pub buf: ImgVec<LinSrgb>,
+    /// AOVs of each pixel, if the frame asks for them
+    pub aovs: Option<ImgVec<AovSample>>,
     pub frame: Arc<FrameData>,
 }
 
-pub struct RenderCtx {
+impl Tile {
+    /// render every pixel of this tile, given the size of the full frame
+    pub fn render<I: Integrator>(&mut self, size: (usize, usize), integrator: &I) {
+        use sampler::{SamplerKind, Independent, Stratified, Halton, Sobol, BlueNoise};
+
+        let seed = self.frame.params.seed;
+        let frame_num = self.frame_num;
+        match self.frame.params.sampler {
+            // pixels would only see black
+            _ if integrator.splats_only() => (),
+            SamplerKind::Independent => self.render_with(size, Independent::new(seed, frame_num), integrator),
+            SamplerKind::Stratified =>
+                self.render_with(size, Stratified::new(seed, frame_num, self.frame.params.samples), integrator),
+            SamplerKind::Halton => self.render_with(size, Halton::new(seed, frame_num), integrator),
+            SamplerKind::Sobol => self.render_with(size, Sobol::new(seed, frame_num), integrator),
+            SamplerKind::BlueNoise => self.render_with(size, BlueNoise::new(seed, frame_num), integrator),
+        }
+
+        // the rest of the tile's share of light, if any, is found all over the frame
+        if let Some(splats) = integrator.splat(self, size) {
+            self.frame.splats.add(splats);
+        }
+    }
+
+    fn render_with<S: Sampler, I: Integrator>(&mut self, size: (usize, usize), mut sampler: S, integrator: &I) {
+        use crate::sample::sample_pixel;
+
+        let pixel_width = 1. / size.1.max(size.0) as f64;
+        for (y, row) in self.buf.rows_mut().enumerate() {
+            let pix_y = y + self.top;
+            for (x, px) in row.iter_mut().enumerate() {
+                let pix_x = x + self.left;
+
+                let (color, aov) = sample_pixel(
+                    &*self.frame.cam,
+                    integrator,
+                    &self.frame.world,
+                    film_point(size, pix_x as f64, pix_y as f64),
+                    pixel_width,
+                    (pix_x, pix_y),
+                    &mut sampler,
+                    &self.frame.params,
+                );
+                *px = color;
+                if let Some(ref mut aovs) = self.aovs {
+                    aovs[(pix_x - self.left, pix_y - self.top)] = aov;
+                }
+            }
+        }
+    }
+
+    /// create an empty tile of a frame
+    pub fn new(frame_num: u32, left: usize, top: usize, w: usize, h: usize, frame: Arc<FrameData>) -> Tile {
+        Tile {
+            buf: ImgVec::new(vec![LinSrgb::default(); w * h], w, h),
+            aovs: match frame.params.aovs {
+                true => Some(ImgVec::new(vec![AovSample::default(); w * h], w, h)),
+                false => None,
+            },
+            top,
+            left,
+            frame,
+            frame_num,
+        }
+    }
+}
+
+pub struct RenderCtx<I> {
+    /// finds the light reaching each pixel
+    pub integrator: I,
     pub input: Receiver<Tile>,
+    /// tiles which were handed to a remote worker that disconnected
+    pub retry: Receiver<Tile>,
     pub output: Sender<Tile>,
     pub size: (usize, usize),
     pub threads: usize,
     pub running: AtomicBool,
 }
 
-impl System for RenderCtx {
-    type Data = ThreadRng;
+/// how long a render thread waits for a tile to retry, once there are no
+/// new tiles, before checking whether it should shut down
+const RETRY_WAIT: Duration = Duration::from_millis(100);
 
-    fn init(&self, _: usize) -> ThreadRng {
-        rand::thread_rng()
-    }
+impl<I: Integrator + 'static> System for RenderCtx<I> {
+    /// whether new tiles may still arrive on `input`
+    type Data = bool;
 
-    fn work(&self, rng: &mut ThreadRng) -> Decision {
-        use crate::sample::sample_pixel;
+    fn init(&self, _: usize) -> bool { true }
 
-        let mut tile = match self.input.recv() {
+    fn work(&self, open: &mut bool) -> Decision {
+        let tile = match self.retry.try_recv() {
+            Some(tile) => Some(tile),
+            None if *open => Select::<Option<Tile>>::new()
+                .recv(&self.retry, |tile| tile)
+                .recv(&self.input, |tile| {
+                    if tile.is_none() { *open = false }
+                    tile
+                })
+                .wait(),
+            // only tiles lost by remote workers are left
+            None => Select::<Option<Tile>>::new()
+                .recv(&self.retry, |tile| tile)
+                .recv(&after(RETRY_WAIT), |_| None)
+                .wait(),
+        };
+        let mut tile = match tile {
             Some(tile) => tile,
             None => return Decision::Again,
         };
 
-        let pixel_width = 1. / self.size.1.max(self.size.0) as f64;
-        for (y, row) in tile.buf.rows_mut().enumerate() {
-            let y = (y + tile.top) as f64 * pixel_width;
-            for (x, px) in row.iter_mut().enumerate() {
-                let x = (x + tile.left) as f64 * pixel_width;
-
-                *px = Srgba::from_linear(Alpha {
-                    color: sample_pixel(
-                        &tile.frame.cam,
-                        &tile.frame.world,
-                        Point2::new(x * 2. - 1., y * 2. - 1.),
-                        pixel_width,
-                        rng,
-                        &tile.frame.params,
-                    ),
-                    alpha: 1.,
-                }).into_format()
-            }
-        }
-
+        tile.render(self.size, &self.integrator);
         self.output.send(tile);
 
         Decision::Incomplete
@@ -97,10 +216,11 @@ impl RenderParams {
     }
 
     pub fn uninitialized_frame(&self) -> FullFrame {
-        use std::mem::uninitialized;
+        let (w, h) = (self.width, self.height);
 
         FullFrame {
-            buf: unsafe { ImgVec::new(vec![uninitialized(); self.width * self.height], self.width, self.height) },
+            buf: ImgVec::new(vec![LinSrgb::default(); w * h], w, h),
+            aovs: None,
             todo_tiles: self.tiles_per_frame(),
         }
     }
@@ -108,25 +228,65 @@ impl RenderParams {
 
 #[derive(Clone, Debug)]
 pub struct FullFrame {
-    pub buf: ImgVec<Srgba<u8>>,
+    pub buf: ImgVec<LinSrgb>,
+    pub aovs: Option<ImgVec<AovSample>>,
     todo_tiles: usize,
 }
 
+/// copy a tile-sized buffer into part of a frame-sized buffer
+fn copy_tile<T: Copy>(to: &mut ImgVec<T>, from: &ImgVec<T>, left: usize, top: usize) {
+    for (to, from) in to.sub_image_mut(
+        left,
+        top,
+        from.width(),
+        from.height()
+    ).rows_mut()
+    .zip(from.rows()) {
+        to.copy_from_slice(from);
+    }
+}
+
+/// add light from one buffer to another of the same size
+fn add_buf(to: &mut ImgVec<LinSrgb>, from: &ImgVec<LinSrgb>) {
+    for (to, from) in to.rows_mut().zip(from.rows()) {
+        for (to, from) in to.iter_mut().zip(from) { *to = *to + *from }
+    }
+}
+
 impl FullFrame {
     pub fn tile_ready(&mut self, tile: &Tile) {
         self.todo_tiles -= 1;
-        for (to, from) in self.buf.sub_image_mut(
-            tile.left,
-            tile.top,
-            tile.buf.width(),
-            tile.buf.height()
-        ).rows_mut()
-        .zip(tile.buf.rows()) {
-            to.copy_from_slice(from);
+        copy_tile(&mut self.buf, &tile.buf, tile.left, tile.top);
+        if let Some(ref aovs) = tile.aovs {
+            let (w, h) = (self.buf.width(), self.buf.height());
+            let frame_aovs = self.aovs
+                .get_or_insert_with(|| ImgVec::new(vec![AovSample::default(); w * h], w, h));
+            copy_tile(frame_aovs, aovs, tile.left, tile.top);
+        }
+
+        // every tile splatted its light before it was finished
+        if self.is_done() {
+            if let Some(splats) = tile.frame.splats.take() {
+                add_buf(&mut self.buf, &splats);
+            }
         }
     }
 
     pub fn is_done(&self) -> bool { self.todo_tiles == 0 }
+
+    /// copy out part of this frame
+    pub fn crop(&self, left: usize, top: usize, width: usize, height: usize) -> FullFrame {
+        fn crop<T: Copy>(buf: &ImgVec<T>, left: usize, top: usize, width: usize, height: usize) -> ImgVec<T> {
+            let sub = buf.sub_image(left, top, width, height);
+            ImgVec::new(sub.rows().flat_map(|r| r.iter().cloned()).collect(), width, height)
+        }
+
+        FullFrame {
+            buf: crop(&self.buf, left, top, width, height),
+            aovs: self.aovs.as_ref().map(|a| crop(a, left, top, width, height)),
+            todo_tiles: 0,
+        }
+    }
 }
 
 impl RenderParams {
@@ -147,13 +307,7 @@ impl RenderParams {
 
                 let w = (self.params.width - self.left).min(self.params.tile_size);
                 let h = (self.params.height - self.top).min(self.params.tile_size);
-                let tile = Tile {
-                    buf: ImgVec::new(vec![Srgba::new(0, 0, 0, 255); w * h], w, h),
-                    top: self.top,
-                    left: self.left,
-                    frame: self.frame.clone(),
-                    frame_num: self.frame_num,
-                };
+                let tile = Tile::new(self.frame_num, self.left, self.top, w, h, self.frame.clone());
 
                 self.left += self.params.tile_size;
                 if self.left >= self.params.width {
@@ -182,32 +336,66 @@ pub enum TickResult {
 }
 
 pub fn render_pipeline(
+    frame_nums: impl IntoIterator<Item=u32>,
     mut frames: impl FnMut(u32) -> Result<Option<FrameData>, Error>,
     mut rendered: impl FnMut(Tile) -> Result<(), Error>,
     mut tick: impl FnMut() -> TickResult,
     tick_ms: u64,
-    params: RenderParams
+    params: RenderParams,
+    integrator: impl Integrator + 'static,
+    remote: Option<Coordinator>,
 ) -> Result<(), Error> {
-    use std::time::Duration;
-    use channel::{bounded, unbounded, tick as tickrecv, Select};
+    use channel::{bounded, unbounded, tick as tickrecv};
     use std::mem::drop;
 
+    if params.threads == 0 && remote.is_none() {
+        return Err(format_err!("nothing can render tiles with no threads and no --listen"));
+    }
+
     let (is, ir) = bounded(params.tile_queue);
     let (cs, cr) = unbounded();
+    let (rs, rr) = unbounded();
     let ticker = tickrecv(Duration::from_millis(tick_ms));
 
+    let remote_running = Arc::new(AtomicBool::new(true));
+    let connected = Arc::new(AtomicUsize::new(0));
+    let remote = remote.map(|remote| {
+        let retry = (rs.clone(), rr.clone());
+        remote.start((params.width, params.height), ir.clone(), cs.clone(), retry, remote_running.clone(), connected.clone())
+    });
+
+    // tell the user once whenever tiles are left waiting for a worker
+    let mut stranded = false;
+    let mut check_workers = || {
+        let idle = params.threads == 0 && connected.load(SeqCst) == 0;
+        if idle && !stranded {
+            eprintln!("tiles are waiting for a worker to connect");
+        }
+        stranded = idle;
+    };
+
     let pool = Pool::start_bg(RenderCtx {
+        integrator,
         input: ir,
+        retry: rr,
         output: cs,
         size: (params.width, params.height),
         threads: params.threads,
         running: AtomicBool::new(true),
     });
 
+    // count tiles so we know when every tile has come back, including
+    // those which had to be rendered a second time
+    let mut sent = 0usize;
+    let mut received = 0usize;
+
     let mut running = true;
-    'frames: for frame_num in 0.. {
+    'frames: for frame_num in frame_nums {
         let frame = match frames(frame_num)? {
-            Some(f) => Arc::new(f),
+            Some(mut f) => {
+                pool.system().integrator.prepare(&mut f, (params.width, params.height), frame_num, params.threads.max(1));
+                Arc::new(f)
+            },
             None => break,
         };
         let mut tiles = params.tiles(frame_num, frame);
@@ -219,27 +407,33 @@ pub fn render_pipeline(
 
             Select::<Result<_, Error>>::new()
                 .recv(&cr, |tile_done| {
-                    if let Some(tile_done) = tile_done { rendered(tile_done)?; }
+                    if let Some(tile_done) = tile_done {
+                        received += 1;
+                        rendered(tile_done)?;
+                    }
                     Ok(())
                 })
                 .recv(&ticker, |_| {
                     if tick() == TickResult::Exit { running = false }
                     Ok(())
                 })
-                .send(&is, || tile.take().unwrap(), || Ok(()))
+                .send(&is, || tile.take().unwrap(), || { sent += 1; Ok(()) })
                 .wait()?;
+            check_workers();
 
             if tile.is_none() { tile = tiles.next() }
         }
     }
 
     drop(is);
-    pool.system().running.store(false, SeqCst);
 
-    while running && (pool.thread_count() > 0 || !cr.is_empty()) {
+    while running && received < sent {
         Select::<Result<_, Error>>::new()
             .recv(&cr, |tile_done| {
-                if let Some(tile_done) = tile_done { rendered(tile_done)?; }
+                if let Some(tile_done) = tile_done {
+                    received += 1;
+                    rendered(tile_done)?;
+                }
                 Ok(())
             })
             .recv(&ticker, |_| {
@@ -247,6 +441,17 @@ pub fn render_pipeline(
                 Ok(())
             })
             .wait()?;
+        check_workers();
+    }
+
+    pool.system().running.store(false, SeqCst);
+    remote_running.store(false, SeqCst);
+    // kept open until now, so render threads wait for retries rather than spin
+    drop(rs);
+
+    // workers are told to finish before the coordinator exits
+    if let Some(remote) = remote {
+        remote.join().map_err(|_| format_err!("coordinator thread panicked"))?;
     }
 
     Ok(())
@@ -1,27 +1,138 @@
-use camera::{Camera, Ray, Sphere, Castable, Impact};
-use nalg::{Vector3, Vector2, Point2, Unit};
-use rand::Rng;
+use std::sync::Arc;
+use camera::{Camera, CameraSample, Ray, Sphere, Castable, Impact};
+use motion::{Motion, Shutter};
+use shape::{Shape, transform_ray};
+use bvh::{Aabb, Bvh};
+use medium::{Medium, Event};
+use subsurface::{Subsurface, fresnel, refract};
+use spectral::Channels;
+use lights::Lights;
+use photon::PhotonMaps;
+use guide::Guide;
+use integrator::Integrator;
+use nalg::{Vector3, Vector2, Point2, Point3, Isometry3, Unit, zero};
+use sampler::{Sampler, SamplerKind};
 use palette::{LinSrgb};
 use stats::{ForPath, BackPath};
+use aov::AovSample;
 
-/// an object in the scene (always a sphere right now)
-#[derive(Clone, Debug)]
-pub struct Object {
-    pub geo: Sphere,
+/// how the surface of an object treats light
+#[derive(Copy, Clone, Debug)]
+pub struct Material {
     pub emission: LinSrgb,
     pub reflectivity: f32,
+    /// light entering the surface and scattering around inside, instead of
+    /// being reflected diffusely
+    pub subsurface: Option<Subsurface>,
+}
+
+impl Material {
+    pub fn new(emission: LinSrgb, reflectivity: f32) -> Material {
+        Material { emission, reflectivity, subsurface: None }
+    }
+
+    /// fraction of incoming light reflected diffusely by this material,
+    /// or scattered back out from beneath its surface
+    ///
+    /// Light reflected specularly is left out, as it shows other surfaces.
+    pub fn albedo(&self) -> LinSrgb {
+        let tint = self.subsurface.as_ref().map_or(LinSrgb::new(1., 1., 1.), |s| s.albedo);
+        tint * (1. - self.reflectivity)
+    }
+}
+
+/// an instance of a shape placed in the scene
+///
+/// Shapes are shared, so copies of the same object only cost a transform
+/// and a material each.
+#[derive(Clone, Debug)]
+pub struct Object {
+    pub shape: Arc<Shape>,
+    /// where the shape is placed in the world
+    pub transform: Isometry3<f64>,
+    pub material: Material,
+    /// movement away from `transform` while the shutter is open, if any
+    pub motion: Option<Motion>,
+    /// name used to refer to this object, e.g. to focus on it
+    pub name: Option<String>,
+    /// medium filling the object, which makes its surface an invisible
+    /// boundary that light passes straight through
+    pub medium: Option<Medium>,
 }
 
 impl Object {
+    /// a sphere with a radius of `r`, centered at `(x, y, z)`
     pub fn new(x: f64, y: f64, z: f64, r: f64, emission: LinSrgb, reflectivity: f32) -> Object {
-        use nalg::Point3;
+        Object::instance(
+            Arc::new(Shape::Sphere(Sphere::new(Point3::origin(), r))),
+            Isometry3::new(Vector3::new(x, y, z), zero()),
+            Material::new(emission, reflectivity),
+        )
+    }
+
+    /// place a shared shape in the world
+    pub fn instance(shape: Arc<Shape>, transform: Isometry3<f64>, material: Material) -> Object {
+        Object { shape, transform, material, motion: None, name: None, medium: None }
+    }
 
-        Object {
-            geo: Sphere::new(Point3::new(x, y, z), r),
-            emission,
-            reflectivity,
+    /// another instance of the same shape somewhere else, optionally made
+    /// of a different material
+    pub fn instanced(&self, transform: Isometry3<f64>, material: Option<Material>) -> Object {
+        Object::instance(self.shape.clone(), transform, material.unwrap_or(self.material))
+    }
+
+    /// give this object a name
+    pub fn named(self, name: &str) -> Object {
+        Object { name: Some(name.to_string()), ..self }
+    }
+
+    /// center of the object's local space, in the world
+    pub fn center(&self) -> Point3<f64> {
+        self.transform * Point3::origin()
+    }
+
+    /// where the object is placed at a point in time
+    pub fn transform_at(&self, time: f64) -> Isometry3<f64> {
+        match self.motion {
+            Some(ref motion) => motion.at(time) * self.transform,
+            None => self.transform,
         }
     }
+
+    /// a box holding the object in the world, wherever it moves while the
+    /// shutter is open
+    pub fn bounds(&self) -> Aabb {
+        let local = self.shape.bounds();
+        match self.motion {
+            Some(ref motion) => {
+                // rotation can swing the shape outside of a box around the
+                // start and end, so look at the steps between too
+                const STEPS: usize = 8;
+                let (t0, t1) = (motion.start.0, motion.end.0);
+                (0..=STEPS)
+                    .map(|i| t0 + (t1 - t0) * i as f64 / STEPS as f64)
+                    .fold(Aabb::empty(), |b, t| b.union(&local.transformed(&self.transform_at(t))))
+            },
+            None => local.transformed(&self.transform),
+        }
+    }
+
+    /// find where a ray hits this object further than `t_min` along it, at
+    /// the ray's time
+    pub fn cast<T>(&self, ray: Ray, t_min: f64, data: T) -> Option<Impact<T>> {
+        // move the ray into the shape's space instead of moving the shape,
+        // which doesn't change distances along it
+        let iso = self.transform_at(ray.time);
+        self.shape.cast_from(transform_ray(&iso.inverse(), ray), t_min, data).map(|i| Impact {
+            norm: Unit::new_unchecked(iso * i.norm.unwrap()),
+            ..i
+        })
+    }
+
+    /// fraction of incoming light reflected by this object
+    pub fn albedo(&self) -> LinSrgb {
+        self.material.albedo()
+    }
 }
 
 impl<'o> Impact<&'o Object> {
@@ -36,6 +147,17 @@ pub struct World {
     pub objects: Vec<Object>,
     pub ambient: LinSrgb,
     pub margin: f64,
+    /// medium filling the space between objects, if any
+    pub fog: Option<Medium>,
+    /// hierarchy over the objects
+    bvh: Bvh,
+    /// emitters which can be aimed at
+    lights: Lights,
+    /// photons traced for the frame, by integrators which use them
+    pub photons: Option<Arc<PhotonMaps>>,
+    /// where light was found to arrive from, for diffuse reflections to be
+    /// aimed at
+    pub guide: Option<Arc<Guide>>,
 }
 
 /// calculate reflection vector
@@ -44,12 +166,11 @@ pub fn reflect(input: Unit<Vector3<f64>>, half: Unit<Vector3<f64>>) -> Unit<Vect
 }
 
 // sample a hemisphere, with cos(theta) weighting
-pub fn cosine_weighted_hemi<R: Rng>(rng: &mut R) -> Unit<Vector3<f64>> {
+pub fn cosine_weighted_hemi((u, v): (f64, f64)) -> Unit<Vector3<f64>> {
     use std::f64::consts::PI;
 
-    let u: f64 = rng.gen_range(0., 1.);
     let r = u.sqrt();
-    let phi = rng.gen_range(0., 2. * PI);
+    let phi = v * 2. * PI;
 
     let x = r * phi.cos();
     let y = r * phi.sin();
@@ -58,122 +179,424 @@ pub fn cosine_weighted_hemi<R: Rng>(rng: &mut R) -> Unit<Vector3<f64>> {
 }
 
 impl World {
+    pub fn new(objects: Vec<Object>, ambient: LinSrgb, margin: f64) -> World {
+        let bounds: Vec<_> = objects.iter().map(|o| o.bounds()).collect();
+        World { bvh: Bvh::new(&bounds), lights: Lights::new(&objects), objects, ambient, margin, fog: None, photons: None, guide: None }
+    }
+
+    pub fn lights(&self) -> &Lights {
+        &self.lights
+    }
+
+    /// find the closest surface along a ray, and the index of its object
+    pub fn hit(&self, ray: Ray) -> Option<Impact<(usize, &Object)>> {
+        // test light direction against objects whose bounds it passes through,
+        // keeping the closest surface (all other surfaces must be behind)
+        self.bvh.cast(ray, |n| {
+            let o = &self.objects[n];
+            // include only surfaces that are at least `margin` units away (excludes current surface)
+            o.cast(ray, self.margin, (n, o))
+        })
+    }
+
+    /// find the closest surface along a ray which isn't only the boundary of
+    /// a medium, passing through those the way the path tracer does
+    ///
+    /// This is for integrators which don't follow light through media.
+    pub fn hit_surface(&self, mut ray: Ray) -> Option<Impact<(usize, &Object)>> {
+        let mut travelled = 0.;
+        loop {
+            let hit = self.hit(ray)?;
+            if hit.data.1.medium.is_none() {
+                return Some(Impact { t: travelled + hit.t, norm: hit.norm, data: hit.data })
+            }
+            travelled += hit.t;
+            ray = Ray::new(ray.origin + hit.t * ray.dir.unwrap(), ray.dir).at_time(ray.time);
+        }
+    }
+
+    /// whether no surface is in the way between two points at a time,
+    /// ignoring the boundaries of media
+    pub fn visible(&self, from: Point3<f64>, to: Point3<f64>, time: f64) -> bool {
+        let d = to - from;
+        let d2 = d.norm_squared();
+        match self.hit_surface(Ray::new(from, Unit::new_normalize(d)).at_time(time)) {
+            Some(hit) => hit.t * hit.t >= d2 * (1. - 1e-4),
+            None => true,
+        }
+    }
+
     /// extend light transport path through world
-    pub fn sample<R: Rng, P: BackPath>(&self, ray: Ray, mut bpath: P, limit: usize, rng: &mut R) -> P::Forward
+    ///
+    /// Colors along the path are carried in the given channels.
+    pub fn sample<S: Sampler, P: BackPath>(&self, ray: Ray, bpath: P, limit: usize, sampler: &mut S, channels: Channels) -> P::Forward
+        where P::Forward: ForPath<Color=LinSrgb, Filter=LinSrgb>
+    {
+        self.trace(ray, bpath, limit, sampler, None, channels)
+    }
+
+    /// extend light transport path, for a ray inside the medium of the given
+    /// object (or the fog, if none)
+    fn trace<S: Sampler, P: BackPath>(
+        &self,
+        ray: Ray,
+        mut bpath: P,
+        limit: usize,
+        sampler: &mut S,
+        inside: Option<usize>,
+        channels: Channels,
+    ) -> P::Forward
         where P::Forward: ForPath<Color=LinSrgb, Filter=LinSrgb>
     {
+        use std::f64::INFINITY;
+
         // find place that light must have come from, if any
-        let hit = self.objects.iter()
-            // test light direction against all objects in scene (slow)
-            .map(|o| o.geo.cast(ray, o))
-            // include only surfaces that could have interacted, and that are at least `margin` units away (excludes current surface)
-            .filter_map(|i| i.filter(|i| i.t > self.margin))
-            // find closest surface (all other surfaces must be behind)
-            .fold(None, |a, bi| match a {
-                None => Some(bi),
-                Some(ai) if ai.t > bi.t => Some(bi),
-                _ => a,
-            });
+        let hit = self.hit(ray);
+        let medium = match inside {
+            Some(n) => self.objects[n].medium.as_ref(),
+            None => self.fog.as_ref(),
+        };
+        let medium = match medium {
+            Some(m) => m,
+            None => return self.surface(ray, hit, bpath, limit, sampler, inside, channels),
+        };
+        let spectral;
+        let medium = match channels {
+            Channels::Rgb => medium,
+            Channels::Spectral(_) => {
+                spectral = channels.medium(medium);
+                &spectral
+            },
+        };
+
+        // light may have been scattered towards us on the way
+        let t_max = hit.as_ref().map_or(INFINITY, |i| i.t);
+        if limit == 0 {
+            // no bounces left to scatter, so only dim whatever is behind
+            let mut fpath = self.surface(ray, hit, bpath, limit, sampler, inside, channels);
+            fpath.filter(medium.transmittance(&ray, t_max, sampler));
+            return fpath
+        }
+
+        let (event, weight) = medium.track(&ray, t_max, &mut bpath, sampler);
+        let mut fpath = match event {
+            Event::Scatter(t) => {
+                let dir = medium.scatter(ray.dir, sampler.next_2d());
+                bpath.bounce();
+                self.trace(
+                    Ray::new(ray.origin + t * ray.dir.unwrap(), dir).at_time(ray.time),
+                    bpath,
+                    limit - 1,
+                    sampler,
+                    inside,
+                    channels,
+                )
+            },
+            Event::Absorb => bpath.source(LinSrgb::new(0., 0., 0.)),
+            Event::Pass => self.surface(ray, hit, bpath, limit, sampler, inside, channels),
+        };
+        fpath.filter(weight);
+        fpath
+    }
+
+    /// extend light transport path from where a ray reaches a surface, or the sky
+    fn surface<S: Sampler, P: BackPath>(
+        &self,
+        ray: Ray,
+        hit: Option<Impact<(usize, &Object)>>,
+        mut bpath: P,
+        limit: usize,
+        sampler: &mut S,
+        inside: Option<usize>,
+        channels: Channels,
+    ) -> P::Forward
+        where P::Forward: ForPath<Color=LinSrgb, Filter=LinSrgb>
+    {
+        // the surface of a medium is only a boundary, so carry on through it
+        if let Some(i) = hit.as_ref() {
+            let (n, o) = i.data;
+            if o.medium.is_some() {
+                let entering = ray.dir.dot(&i.norm) < 0.;
+                return self.trace(
+                    Ray::new(ray.origin + i.t * ray.dir.unwrap(), ray.dir).at_time(ray.time),
+                    bpath,
+                    limit,
+                    sampler,
+                    if entering { Some(n) } else { None },
+                    channels,
+                )
+            }
+        }
+        let hit = hit.map(|i| (i.data.0, Impact { t: i.t, norm: i.norm, data: i.data.1 }));
 
         match (hit, limit) {
-            (None, _) => bpath.source(self.ambient), // light came from sky
-            (Some(i), 0) => bpath.source(i.data.emission), // reached limit, assume light came from surface
-            (Some(i), _) => { // light came from surface (maybe reflected?)
-                let x;
+            (None, _) => bpath.source(channels.radiance(self.ambient)), // light came from sky
+            (Some((_, i)), 0) => bpath.source(channels.radiance(i.data.material.emission)), // reached limit, assume light came from surface
+            (Some((n, i)), _) => { // light came from surface (maybe reflected?)
                 let filter;
 
                 // assume light came from surface 10% of the time
                 const EMISSION_P: f32 = 0.1;
 
-                let mut fpath = if rng.gen_range(0., 1.) < EMISSION_P {
+                let mut fpath = if sampler.next() < EMISSION_P as f64 {
                     // assume light energy came from surface
                     bpath.decide(EMISSION_P);
 
-                    filter = 1.;
-                    bpath.source(i.data.emission)
+                    filter = LinSrgb::new(1., 1., 1.);
+                    bpath.source(channels.radiance(i.data.material.emission))
                 } else {
                     // assume light reflected off surface
                     bpath.decide_not(EMISSION_P);
 
-                    let refl = i.data.reflectivity;
+                    let point = ray.origin + i.t * ray.dir.unwrap();
+                    let mut next = Ray::new(point, ray.dir);
+                    let refl = i.data.material.reflectivity;
                     let not_refl = 1. - refl;
-                    if refl > rng.gen_range(0., 1.) {
+                    if refl as f64 > sampler.next() {
                         // assume specular reflection
                         bpath.decide(refl);
 
-                        x = reflect(ray.dir, i.halfway());
-                        filter = refl;
+                        next.dir = reflect(ray.dir, i.halfway());
+                        filter = LinSrgb::new(refl, refl, refl);
+                    } else if let Some(ref sub) = i.data.material.subsurface {
+                        // assume light came through the surface from inside
+                        bpath.decide_not(refl);
+
+                        match self.subsurface(ray, &i, n, sub, &mut bpath, sampler, channels) {
+                            Some((out, weight)) => {
+                                next = out;
+                                filter = weight * not_refl;
+                            },
+                            None => {
+                                // absorbed inside
+                                let mut fpath = bpath.source(LinSrgb::new(0., 0., 0.));
+                                fpath.filter(LinSrgb::new(not_refl, not_refl, not_refl));
+                                return fpath
+                            },
+                        }
                     } else {
                         // assume diffuse reflection
                         bpath.decide_not(refl);
 
-                        let cwh = cosine_weighted_hemi(rng);
-                        x = Unit::new_unchecked(i.surface() * cwh.unwrap());
-                        filter = not_refl;
+                        match self.guide {
+                            Some(ref guide) => {
+                                // the direction may not have been picked by
+                                // cos(theta), so weigh it by how likely it was
+                                use std::f64::consts::PI;
+
+                                let (dir, pdf) = guide.sample(point, i.norm, sampler);
+                                let cos = dir.dot(&i.norm);
+                                if cos <= 0. || pdf <= 0. {
+                                    // no light is reflected from below the surface
+                                    let mut fpath = bpath.source(LinSrgb::new(0., 0., 0.));
+                                    fpath.filter(LinSrgb::new(0., 0., 0.));
+                                    return fpath
+                                }
+                                next.dir = dir;
+                                let weight = not_refl * (cos / (PI * pdf)) as f32;
+                                filter = LinSrgb::new(weight, weight, weight);
+                            },
+                            None => {
+                                let cwh = cosine_weighted_hemi(sampler.next_2d());
+                                next.dir = Unit::new_unchecked(i.surface() * cwh.unwrap());
+                                filter = LinSrgb::new(not_refl, not_refl, not_refl);
+                            },
+                        }
                     }
 
                     // extend transport path again
-                    self.sample(
-                        Ray::new(ray.origin + i.t * ray.dir.unwrap(), x),
-                        bpath,
-                        limit - 1,
-                        rng,
-                    )
+                    bpath.bounce();
+                    self.trace(next.at_time(ray.time), bpath, limit - 1, sampler, inside, channels)
                 };
 
-                fpath.filter(LinSrgb::new(filter, filter, filter));
+                fpath.filter(filter);
                 fpath
             }
         }
     }
+
+    /// follow light backwards through the surface of a subsurface material,
+    /// either reflected off the boundary or walking around inside the object
+    /// before it came in
+    ///
+    /// Returns the ray light arrived along and the filter to apply to it, or
+    /// `None` if it was absorbed inside.
+    pub fn subsurface<S: Sampler, P: BackPath>(
+        &self,
+        ray: Ray,
+        hit: &Impact<&Object>,
+        n: usize,
+        sub: &Subsurface,
+        bpath: &mut P,
+        sampler: &mut S,
+        channels: Channels,
+    ) -> Option<(Ray, LinSrgb)> {
+        use std::f64::INFINITY;
+
+        // a dispersive surface leaves only the hero wavelength
+        let (ior, mut weight) = sub.ior.in_channels(channels);
+        let point = ray.origin + hit.t * ray.dir.unwrap();
+
+        // rays started from inside see the boundary the other way round
+        let outside = ray.dir.dot(&hit.norm) < 0.;
+        let (norm, eta) = if outside { (hit.norm, 1. / ior) } else { (-hit.norm, ior) };
+        let f = fresnel(-ray.dir.dot(&norm), eta) as f32;
+        if sampler.next() < f as f64 {
+            bpath.decide(f);
+            return Some((Ray::new(point, reflect(ray.dir, norm)), weight * f))
+        }
+        bpath.decide_not(f);
+        weight = weight * (1. - f);
+        let mut walk = Ray::new(point, refract(ray.dir, norm, eta)?).at_time(ray.time);
+        if !outside {
+            // already left the object
+            return Some((walk, weight))
+        }
+
+        let object = &self.objects[n];
+        let medium = sub.medium(channels);
+        for _ in 0..MAX_WALK {
+            // the walk only sees its own object, anything else inside is ignored
+            let exit = object.cast(walk, self.margin, ());
+            let t_max = exit.as_ref().map_or(INFINITY, |i| i.t);
+            let (event, w) = medium.track(&walk, t_max, bpath, sampler);
+            weight = weight * w;
+
+            match (event, exit) {
+                (Event::Scatter(t), _) => {
+                    let dir = medium.scatter(walk.dir, sampler.next_2d());
+                    walk = Ray::new(walk.origin + t * walk.dir.unwrap(), dir).at_time(ray.time);
+                },
+                (Event::Absorb, _) => return None,
+                (Event::Pass, Some(exit)) => {
+                    // reached the surface again, from inside
+                    let point = walk.origin + exit.t * walk.dir.unwrap();
+                    let norm = -exit.norm;
+                    let f = fresnel(-walk.dir.dot(&norm), ior) as f32;
+                    if sampler.next() < f as f64 {
+                        bpath.decide(f);
+                        weight = weight * f;
+                        walk = Ray::new(point, reflect(walk.dir, norm)).at_time(ray.time);
+                    } else {
+                        bpath.decide_not(f);
+                        let dir = refract(walk.dir, norm, ior)?;
+                        return Some((Ray::new(point, dir), weight * (1. - f)))
+                    }
+                },
+                // the object isn't closed, so the light is lost
+                (Event::Pass, None) => return None,
+            }
+        }
+        None
+    }
 }
 
+/// most scattering events in a subsurface walk before giving up on it
+const MAX_WALK: usize = 256;
+
+
 #[derive(Copy, Clone, Debug)]
 pub struct SampleParams {
     /// number of samples per pixel
     pub samples: usize,
-    /// maximum number of bounces
-    pub bounce_limit: usize,
+    /// seed which all random decisions are derived from
+    pub seed: u64,
+    /// how sample values are distributed
+    pub sampler: SamplerKind,
+    /// also calculate AOVs at each pixel
+    pub aovs: bool,
+    /// when light reaches the film during each frame
+    pub shutter: Shutter,
+    /// carry light of a few wavelengths along each path, instead of RGB
+    pub spectral: bool,
+}
+
+/// pick a ray the camera sees along from a point on the film, and the
+/// channels light along it is carried in
+pub fn camera_ray<S: Sampler, C: Camera<CameraSample> + ?Sized>(
+    cam: &C,
+    film: Point2<f64>,
+    sampler: &mut S,
+    params: &SampleParams,
+) -> Option<(Ray, Channels)> {
+    // light doesn't pass through the exact center of aperture
+    // the camera decides where on its lens this lands
+    let (lu, lv) = sampler.next_2d();
+    let lens = Vector2::new(lu, lv);
+
+    // light doesn't arrive at the exact frame time either
+    let time = params.shutter.sample(sampler.next());
+
+    // nor is it only red, green and blue
+    let channels = if params.spectral { Channels::sample(sampler.next()) } else { Channels::Rgb };
+
+    cam.look(CameraSample { film, lens, time }).map(|r| (r.at_time(time), channels))
 }
 
 /// Get value of a single pixel
-pub fn sample_pixel<R: Rng, C: Camera<(Point2<f64>, Vector2<f64>)>>(
+pub fn sample_pixel<S: Sampler, C: Camera<CameraSample> + ?Sized, I: Integrator>(
     cam: &C, // camera ray calculator
+    integrator: &I, // finds light arriving along camera rays
     world: &World, // world object
     point: Point2<f64>, // upper-left corner of pixel on film
     pixel_width: f64, // width of a single pixel on film
-    rng: &mut R, // random number generator
+    pixel: (usize, usize), // pixel coordinates, used to start samples
+    sampler: &mut S, // source of sample values
     params: &SampleParams, // parameters for pixel sampling
-) -> LinSrgb {
-    use stats::MulBackPath;
-    use std::f64::consts::PI;
-
-    // initialize the sum
+) -> (LinSrgb, AovSample) {
+    // initialize the sums
     let mut val = LinSrgb::new(0., 0., 0.);
+    let mut aov = AovSample::default();
+    // surfaces are only averaged over samples which hit one
+    let mut hits = 0;
 
     // sample many times
-    for _ in 0..params.samples {
+    for i in 0..params.samples {
+        sampler.start(pixel, i);
+
         // light doesn't strike the exact corner of the pixel
         // offset by random amount (cartesian since pixel is square)
-        let offset = Vector2::new(rng.gen_range(0., pixel_width), rng.gen_range(0., pixel_width));
-
-        // light doesn't pass through the exact center of aperture
-        // offset by random amount (polar since aperture is round)
-        let defoc_r = 0.2 * rng.gen_range(0f64, 1f64).sqrt();
-        let defoc_theta = rng.gen_range(0., 2. * PI);
-        let defoc = Vector2::new(defoc_r * defoc_theta.sin(), defoc_r * defoc_theta.cos());
+        let (ox, oy) = sampler.next_2d();
+        let offset = Vector2::new(ox * pixel_width, oy * pixel_width);
 
         // create ray to trace
-        let ray = match cam.look((point + offset, defoc)) {
+        let (ray, channels) = match camera_ray(cam, point + offset, sampler, params) {
             Some(r) => r,
             None => continue,
         };
 
+        // record what the camera sees first
+        if params.aovs {
+            match world.hit_surface(ray) {
+                Some(hit) => {
+                    let (n, o) = hit.data;
+                    aov.depth += hit.t as f32;
+                    hits += 1;
+                    for (a, &b) in aov.normal.iter_mut().zip(hit.norm.as_ref().iter()) { *a += b as f32 }
+                    aov.albedo = aov.albedo + o.albedo();
+                    if i == 0 { aov.id = n as u32 + 1 }
+                },
+                None => aov.albedo = aov.albedo + world.ambient,
+            }
+        }
+
         // get transport path of light through world
-        let path = world.sample(ray, MulBackPath::new(), params.bounce_limit, rng);
-        val = val + path.lum(); // add luminance to sum
+        let light = integrator.radiance(world, ray, sampler, channels);
+        val = val + channels.to_rgb(light.total()); // add luminance to sum
+        aov.emission = aov.emission + channels.to_rgb(light.emission);
+        aov.direct = aov.direct + channels.to_rgb(light.direct);
+        aov.indirect = aov.indirect + channels.to_rgb(light.indirect);
     }
 
     // each path additionally has a 1/n probability of occurring relative to other paths
-    val / params.samples as f32 // return sum
+    let n = params.samples as f32;
+    if hits > 0 {
+        aov.depth /= hits as f32;
+        for a in aov.normal.iter_mut() { *a /= hits as f32 }
+    }
+    aov.albedo = aov.albedo / n;
+    aov.direct = aov.direct / n;
+    aov.indirect = aov.indirect / n;
+    aov.emission = aov.emission / n;
+    (val / n, aov) // return sum
 }
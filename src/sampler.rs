@@ -0,0 +1,434 @@
+//! Sources of sample values for the renderer.
+//!
+//! A sample is a point in a high dimensional unit hypercube: the first two
+//! dimensions place it on the pixel, the next two on the lens, and the rest
+//! drive each decision along the light path. Samplers hand out those
+//! dimensions in order. Better distributed points mean less noise for the same
+//! number of samples.
+
+use std::str::FromStr;
+use std::rc::Rc;
+use rand::{Rng, SeedableRng};
+use rand::prng::XorShiftRng;
+use failure::{Error, format_err};
+
+pub trait Sampler {
+    /// start the given sample of a pixel, going back to the first dimension
+    fn start(&mut self, pixel: (usize, usize), sample: usize);
+
+    /// get the next dimension of the current sample, in `[0, 1)`
+    fn next(&mut self) -> f64;
+
+    /// get the next two dimensions of the current sample, in `[0, 1)`
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.next(), self.next())
+    }
+}
+
+/// scramble the bits of a value (splitmix64 finalizer)
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// combine several values into a single well-distributed hash
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x2545f4914f6cdd1d, |h, &v| mix(h ^ v.wrapping_mul(0x9e3779b97f4a7c15)))
+}
+
+/// convert 32 random bits into a value in `[0, 1)`
+fn unit(bits: u32) -> f64 {
+    bits as f64 / 4294967296.
+}
+
+/// create the random number generator for a single sample of a single pixel
+///
+/// Every sample gets an independent generator, so the result does not depend
+/// on which thread renders which tile, or in what order.
+pub fn sample_rng(seed: u64, frame_num: u32, pixel: (usize, usize), sample: usize) -> XorShiftRng {
+    let mut h = hash(&[seed, frame_num as u64, pixel.0 as u64, pixel.1 as u64, sample as u64]);
+
+    let mut bytes = [0; 16];
+    for (i, b) in bytes.iter_mut().enumerate() {
+        if i == 8 { h = mix(h) }
+        *b = (h >> (8 * (i % 8))) as u8;
+    }
+    XorShiftRng::from_seed(bytes)
+}
+
+/// which sampler to render with
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+
+impl FromStr for SamplerKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SamplerKind, Error> {
+        Ok(match s {
+            "independent" | "random" => SamplerKind::Independent,
+            "stratified" | "jittered" => SamplerKind::Stratified,
+            "halton" => SamplerKind::Halton,
+            "sobol" => SamplerKind::Sobol,
+            "bluenoise" | "blue-noise" => SamplerKind::BlueNoise,
+            _ => return Err(format_err!("unknown sampler \"{}\"", s)),
+        })
+    }
+}
+
+/// independent uniform random values for every dimension
+pub struct Independent {
+    seed: u64,
+    frame_num: u32,
+    rng: XorShiftRng,
+}
+
+impl Independent {
+    pub fn new(seed: u64, frame_num: u32) -> Independent {
+        Independent { seed, frame_num, rng: sample_rng(seed, frame_num, (0, 0), 0) }
+    }
+}
+
+impl Sampler for Independent {
+    fn start(&mut self, pixel: (usize, usize), sample: usize) {
+        self.rng = sample_rng(self.seed, self.frame_num, pixel, sample);
+    }
+
+    fn next(&mut self) -> f64 {
+        self.rng.gen_range(0., 1.)
+    }
+}
+
+/// pseudo-random permutation of `i` within `0..len` (Kensler 2013)
+fn permute(mut i: u32, len: u32, p: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len { break }
+    }
+    (i.wrapping_add(p)) % len
+}
+
+/// jittered samples, with every dimension split into one stratum per sample
+///
+/// Strata are shuffled independently for each pixel and dimension, so
+/// dimensions do not correlate with each other. Pairs of dimensions are
+/// stratified on a grid when the number of samples is square, and as
+/// n-rooks otherwise.
+pub struct Stratified {
+    seed: u64,
+    frame_num: u32,
+    samples: u32,
+    key: u64,
+    sample: u32,
+    dim: u64,
+}
+
+impl Stratified {
+    pub fn new(seed: u64, frame_num: u32, samples: usize) -> Stratified {
+        Stratified {
+            seed,
+            frame_num,
+            samples: samples.max(1) as u32,
+            key: 0,
+            sample: 0,
+            dim: 0,
+        }
+    }
+
+    /// stratum of the current sample and random jitter within it
+    fn stratum(&mut self) -> (u32, u32) {
+        let h = hash(&[self.key, self.dim]);
+        self.dim += 1;
+        let jitter = hash(&[h, self.sample as u64]);
+        (permute(self.sample % self.samples, self.samples, h as u32), jitter as u32)
+    }
+}
+
+impl Sampler for Stratified {
+    fn start(&mut self, pixel: (usize, usize), sample: usize) {
+        self.key = hash(&[self.seed, self.frame_num as u64, pixel.0 as u64, pixel.1 as u64]);
+        self.sample = sample as u32;
+        self.dim = 0;
+    }
+
+    fn next(&mut self) -> f64 {
+        let (s, j) = self.stratum();
+        (s as f64 + unit(j)) / self.samples as f64
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let dim = self.dim;
+        let (s, j) = self.stratum();
+        let j2 = mix(j as u64);
+        let n = self.samples;
+
+        // lay strata out on a square grid if they fit one exactly
+        let side = (n as f64).sqrt().round() as u32;
+        if side * side == n {
+            return (
+                ((s % side) as f64 + unit(j)) / side as f64,
+                ((s / side) as f64 + unit(j2 as u32)) / side as f64,
+            );
+        }
+
+        // otherwise n-rooks, with one sample in each column and each row,
+        // rows being shuffled against columns
+        let row = permute(s, n, hash(&[self.key, dim, 1]) as u32);
+        (
+            (s as f64 + unit(j)) / n as f64,
+            (row as f64 + unit(j2 as u32)) / n as f64,
+        )
+    }
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+fn radical_inverse(base: u64, mut i: u64) -> f64 {
+    let inv = 1. / base as f64;
+    let mut f = inv;
+    let mut r = 0.;
+    while i > 0 {
+        r += (i % base) as f64 * f;
+        i /= base;
+        f *= inv;
+    }
+    r
+}
+
+/// the Halton sequence, toroidally shifted by a random amount for each pixel
+///
+/// Dimensions past the prime table fall back to independent random values.
+pub struct Halton {
+    seed: u64,
+    frame_num: u32,
+    key: u64,
+    sample: u64,
+    dim: usize,
+}
+
+impl Halton {
+    pub fn new(seed: u64, frame_num: u32) -> Halton {
+        Halton { seed, frame_num, key: 0, sample: 0, dim: 0 }
+    }
+}
+
+impl Sampler for Halton {
+    fn start(&mut self, pixel: (usize, usize), sample: usize) {
+        self.key = hash(&[self.seed, self.frame_num as u64, pixel.0 as u64, pixel.1 as u64]);
+        self.sample = sample as u64;
+        self.dim = 0;
+    }
+
+    fn next(&mut self) -> f64 {
+        let shift = unit(hash(&[self.key, self.dim as u64]) as u32);
+        let v = match PRIMES.get(self.dim) {
+            Some(&base) => radical_inverse(base, self.sample),
+            None => unit(hash(&[self.key, self.dim as u64, self.sample]) as u32),
+        };
+        self.dim += 1;
+        (v + shift).fract()
+    }
+}
+
+fn reverse_bits(mut x: u32) -> u32 {
+    x = (x << 16) | (x >> 16);
+    x = ((x & 0x00ff00ff) << 8) | ((x & 0xff00ff00) >> 8);
+    x = ((x & 0x0f0f0f0f) << 4) | ((x & 0xf0f0f0f0) >> 4);
+    x = ((x & 0x33333333) << 2) | ((x & 0xcccccccc) >> 2);
+    ((x & 0x55555555) << 1) | ((x & 0xaaaaaaaa) >> 1)
+}
+
+/// first two dimensions of the Sobol sequence, as 32 bit fractions
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let x = reverse_bits(index);
+    let mut y = 0;
+    let mut v = 1 << 31;
+    let mut i = index;
+    while i != 0 {
+        if i & 1 != 0 { y ^= v }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    (x, y)
+}
+
+/// hash-based Owen scrambling (Burley 2020)
+fn owen_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = reverse_bits(x);
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    reverse_bits(x)
+}
+
+/// a single 2D point of a padded, shuffled and Owen scrambled Sobol sequence
+fn owen_sobol_2d(index: u32, key: u64) -> (f64, f64) {
+    let index = owen_scramble(index, hash(&[key, 0]) as u32);
+    let (x, y) = sobol_2d(index);
+    (
+        unit(owen_scramble(x, hash(&[key, 1]) as u32)),
+        unit(owen_scramble(y, hash(&[key, 2]) as u32)),
+    )
+}
+
+/// Owen scrambled Sobol points, padded with independent shuffles for
+/// every pair of dimensions
+pub struct Sobol {
+    seed: u64,
+    frame_num: u32,
+    key: u64,
+    sample: u32,
+    dim: u64,
+}
+
+impl Sobol {
+    pub fn new(seed: u64, frame_num: u32) -> Sobol {
+        Sobol { seed, frame_num, key: 0, sample: 0, dim: 0 }
+    }
+}
+
+impl Sampler for Sobol {
+    fn start(&mut self, pixel: (usize, usize), sample: usize) {
+        self.key = hash(&[self.seed, self.frame_num as u64, pixel.0 as u64, pixel.1 as u64]);
+        self.sample = sample as u32;
+        self.dim = 0;
+    }
+
+    fn next(&mut self) -> f64 {
+        self.next_2d().0
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let key = hash(&[self.key, self.dim]);
+        self.dim += 1;
+        owen_sobol_2d(self.sample, key)
+    }
+}
+
+/// width and height of the tiled blue noise mask
+const MASK_SIZE: usize = 32;
+
+/// create a tileable blue noise dither mask with values in `[0, 1)`
+///
+/// Pixels are ranked by repeatedly picking the one farthest from all pixels
+/// already ranked (the void phase of Ulichney's void-and-cluster method).
+fn blue_noise_mask() -> Vec<f64> {
+    const SIGMA: f64 = 1.5;
+    const N: usize = MASK_SIZE * MASK_SIZE;
+
+    let mut energy = vec![0f64; N];
+    let mut rank = vec![None; N];
+    let mut next = 0;
+    for r in 0..N {
+        rank[next] = Some(r);
+
+        // splat energy with wrap-around
+        let (px, py) = ((next % MASK_SIZE) as isize, (next / MASK_SIZE) as isize);
+        for (i, e) in energy.iter_mut().enumerate() {
+            let wrap = |d: isize| { let d = d.abs(); d.min(MASK_SIZE as isize - d) as f64 };
+            let dx = wrap((i % MASK_SIZE) as isize - px);
+            let dy = wrap((i / MASK_SIZE) as isize - py);
+            *e += (-(dx * dx + dy * dy) / (2. * SIGMA * SIGMA)).exp();
+        }
+
+        // find the largest void
+        next = (0..N)
+            .filter(|&i| rank[i].is_none())
+            .min_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap())
+            .unwrap_or(0);
+    }
+
+    rank.into_iter().map(|r| (r.unwrap() as f64 + 0.5) / N as f64).collect()
+}
+
+thread_local! {
+    static MASK: Rc<Vec<f64>> = Rc::new(blue_noise_mask());
+}
+
+/// an Owen scrambled Sobol sequence shared by every pixel, but shifted by a
+/// blue noise mask so that the error between neighboring pixels is
+/// uncorrelated and appears as high frequency noise
+pub struct BlueNoise {
+    seed: u64,
+    frame_num: u32,
+    mask: Rc<Vec<f64>>,
+    pixel: (usize, usize),
+    sample: u32,
+    dim: u64,
+}
+
+impl BlueNoise {
+    pub fn new(seed: u64, frame_num: u32) -> BlueNoise {
+        BlueNoise {
+            seed,
+            frame_num,
+            mask: MASK.with(|m| m.clone()),
+            pixel: (0, 0),
+            sample: 0,
+            dim: 0,
+        }
+    }
+
+    /// mask value for the current pixel, at a different offset for each key
+    fn dither(&self, key: u64) -> f64 {
+        let x = (self.pixel.0 + (key as usize % MASK_SIZE)) % MASK_SIZE;
+        let y = (self.pixel.1 + ((key >> 32) as usize % MASK_SIZE)) % MASK_SIZE;
+        self.mask[y * MASK_SIZE + x]
+    }
+}
+
+impl Sampler for BlueNoise {
+    fn start(&mut self, pixel: (usize, usize), sample: usize) {
+        self.pixel = pixel;
+        self.sample = sample as u32;
+        self.dim = 0;
+    }
+
+    fn next(&mut self) -> f64 {
+        self.next_2d().0
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let key = hash(&[self.seed, self.frame_num as u64, self.dim]);
+        self.dim += 1;
+        let (x, y) = owen_sobol_2d(self.sample, key);
+        (
+            (x + self.dither(hash(&[key, 3]))).fract(),
+            (y + self.dither(hash(&[key, 4]))).fract(),
+        )
+    }
+}
@@ -0,0 +1,213 @@
+//! Shapes described by signed distance fields, which are drawn by sphere
+//! tracing rather than being turned into a mesh.
+//!
+//! Fields can be written as expressions, like
+//! `smooth_union(0.5, torus(2, 0.5), translate(0, 1, 0, box(1, 1, 1, 0.2)))`.
+
+use std::str::FromStr;
+use nalg::{Point3, Vector3, Unit};
+use failure::{Error, format_err};
+use camera::{Ray, Castable, Impact};
+use bvh::Aabb;
+use expr::Call;
+
+/// a signed distance field: negative inside a shape, positive outside, and
+/// never more than the distance to the surface
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sdf {
+    Sphere { radius: f64 },
+    /// a box with half its size along each axis, and edges rounded off by `rounding`
+    Box { half: Vector3<f64>, rounding: f64 },
+    /// a ring around the y axis
+    Torus { major: f64, minor: f64 },
+    Mandelbulb { power: f64, iterations: u32 },
+    Union(Box<Sdf>, Box<Sdf>),
+    Intersection(Box<Sdf>, Box<Sdf>),
+    /// the first shape, with the second cut out of it
+    Subtraction(Box<Sdf>, Box<Sdf>),
+    /// like the plain operators, but blending across a distance of `k`
+    SmoothUnion(f64, Box<Sdf>, Box<Sdf>),
+    SmoothIntersection(f64, Box<Sdf>, Box<Sdf>),
+    SmoothSubtraction(f64, Box<Sdf>, Box<Sdf>),
+    Translate(Vector3<f64>, Box<Sdf>),
+}
+
+/// polynomial smooth minimum, which rounds off where `a` and `b` meet
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    let h = (0.5 + 0.5 * (b - a) / k).max(0.).min(1.);
+    b * (1. - h) + a * h - k * h * (1. - h)
+}
+
+impl Sdf {
+    /// signed distance from a point to the surface
+    pub fn distance(&self, p: Point3<f64>) -> f64 {
+        match *self {
+            Sdf::Sphere { radius } => p.coords.norm() - radius,
+            Sdf::Box { half, rounding } => {
+                let q = p.coords.map(f64::abs) - half + Vector3::repeat(rounding);
+                q.map(|v| v.max(0.)).norm() + q.x.max(q.y).max(q.z).min(0.) - rounding
+            },
+            Sdf::Torus { major, minor } => {
+                let ring = (p.x * p.x + p.z * p.z).sqrt() - major;
+                (ring * ring + p.y * p.y).sqrt() - minor
+            },
+            Sdf::Mandelbulb { power, iterations } => {
+                // distance estimate from the derivative of the iterated function
+                let mut z = p.coords;
+                let mut dr = 1.;
+                let mut r = z.norm();
+                for _ in 0..iterations {
+                    if r > 2. || r == 0. { break }
+                    let theta = (z.z / r).acos() * power;
+                    let phi = z.y.atan2(z.x) * power;
+                    dr = r.powf(power - 1.) * power * dr + 1.;
+                    z = r.powf(power) * Vector3::new(
+                        theta.sin() * phi.cos(),
+                        theta.sin() * phi.sin(),
+                        theta.cos(),
+                    ) + p.coords;
+                    r = z.norm();
+                }
+                if r == 0. { 0. } else { 0.5 * r.ln() * r / dr }
+            },
+            Sdf::Union(ref a, ref b) => a.distance(p).min(b.distance(p)),
+            Sdf::Intersection(ref a, ref b) => a.distance(p).max(b.distance(p)),
+            Sdf::Subtraction(ref a, ref b) => a.distance(p).max(-b.distance(p)),
+            Sdf::SmoothUnion(k, ref a, ref b) => smooth_min(a.distance(p), b.distance(p), k),
+            Sdf::SmoothIntersection(k, ref a, ref b) => -smooth_min(-a.distance(p), -b.distance(p), k),
+            Sdf::SmoothSubtraction(k, ref a, ref b) => -smooth_min(-a.distance(p), b.distance(p), k),
+            Sdf::Translate(offset, ref a) => a.distance(p - offset),
+        }
+    }
+
+    /// direction in which the distance grows fastest, which is the surface
+    /// normal on the surface
+    pub fn gradient(&self, p: Point3<f64>) -> Unit<Vector3<f64>> {
+        const H: f64 = 1e-5;
+        let d = |v: Vector3<f64>| self.distance(p + v) - self.distance(p - v);
+        Unit::new_normalize(Vector3::new(
+            d(Vector3::new(H, 0., 0.)),
+            d(Vector3::new(0., H, 0.)),
+            d(Vector3::new(0., 0., H)),
+        ))
+    }
+
+    /// a box holding the whole shape
+    pub fn bounds(&self) -> Aabb {
+        match *self {
+            Sdf::Sphere { radius } => Aabb::ball(Point3::origin(), radius),
+            Sdf::Box { half, .. } => Aabb { min: Point3::from_coordinates(-half), max: Point3::from_coordinates(half) },
+            Sdf::Torus { major, minor } => {
+                let (w, h) = (major + minor, minor);
+                Aabb { min: Point3::new(-w, -h, -w), max: Point3::new(w, h, w) }
+            },
+            // the set itself reaches about 1.1 from the origin
+            Sdf::Mandelbulb { .. } => Aabb::ball(Point3::origin(), 1.2),
+            Sdf::Union(ref a, ref b) => a.bounds().union(&b.bounds()),
+            Sdf::Intersection(ref a, ref b) | Sdf::SmoothIntersection(_, ref a, ref b) =>
+                a.bounds().intersection(&b.bounds()),
+            Sdf::Subtraction(ref a, _) | Sdf::SmoothSubtraction(_, ref a, _) => a.bounds(),
+            // blending bulges out by at most a quarter of `k`
+            Sdf::SmoothUnion(k, ref a, ref b) => a.bounds().union(&b.bounds()).grown(k / 4.),
+            Sdf::Translate(offset, ref a) => {
+                let b = a.bounds();
+                Aabb { min: b.min + offset, max: b.max + offset }
+            },
+        }
+    }
+}
+
+/// how close a ray must get to the surface to count as a hit
+const EPSILON: f64 = 1e-4;
+/// most steps a ray may take before giving up
+const MAX_STEPS: usize = 512;
+
+/// a distance field ready to be cast against
+#[derive(Clone, Debug)]
+pub struct SdfShape {
+    pub sdf: Sdf,
+    bounds: Aabb,
+}
+
+impl SdfShape {
+    pub fn new(sdf: Sdf) -> SdfShape {
+        SdfShape { bounds: sdf.bounds(), sdf }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+impl Castable for SdfShape {
+    fn cast<T>(&self, ray: Ray, data: T) -> Option<Impact<T>> {
+        use std::f64::INFINITY;
+
+        let (mut t, exit) = self.bounds.span(&ray, INFINITY)?;
+        let dir = ray.dir.unwrap();
+        let at = |t: f64| self.sdf.distance(ray.origin + dir * t);
+
+        // step off any surface the ray starts on, then see which side it is on
+        let mut steps = 0;
+        let mut d = at(t);
+        while d.abs() < EPSILON && steps < MAX_STEPS {
+            t += EPSILON;
+            d = at(t);
+            steps += 1;
+        }
+        let side = d.signum();
+
+        // the field says how far the ray can safely go without passing the surface
+        while steps < MAX_STEPS && t <= exit {
+            let step = d * side;
+            if step < EPSILON {
+                let norm = self.sdf.gradient(ray.origin + dir * t);
+                return Some(Impact { t, norm, data })
+            }
+            t += step;
+            d = at(t);
+            steps += 1;
+        }
+        None
+    }
+}
+
+/// make a field from an operator name and its arguments
+fn build(call: Call) -> Result<Sdf, Error> {
+    let Call { name, numbers, args } = call;
+    let mut fields = args.into_iter()
+        .map(|a| build(a).map(Box::new))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // combine any number of fields with a binary operator
+    let fold = |fields: Vec<Box<Sdf>>, op: &dyn Fn(Box<Sdf>, Box<Sdf>) -> Sdf| {
+        let mut fields = fields.into_iter();
+        let first = *fields.next().unwrap();
+        fields.fold(first, |a, b| op(Box::new(a), b))
+    };
+
+    match (&name[..], &numbers[..], fields.len()) {
+        ("sphere", &[radius], 0) => Ok(Sdf::Sphere { radius }),
+        ("box", &[x, y, z], 0) => Ok(Sdf::Box { half: Vector3::new(x, y, z), rounding: 0. }),
+        ("box", &[x, y, z, rounding], 0) => Ok(Sdf::Box { half: Vector3::new(x, y, z), rounding }),
+        ("torus", &[major, minor], 0) => Ok(Sdf::Torus { major, minor }),
+        ("mandelbulb", &[], 0) => Ok(Sdf::Mandelbulb { power: 8., iterations: 12 }),
+        ("mandelbulb", &[power], 0) => Ok(Sdf::Mandelbulb { power, iterations: 12 }),
+        ("union", &[], n) if n >= 2 => Ok(fold(fields, &Sdf::Union)),
+        ("intersect", &[], n) if n >= 2 => Ok(fold(fields, &Sdf::Intersection)),
+        ("subtract", &[], n) if n >= 2 => Ok(fold(fields, &Sdf::Subtraction)),
+        ("smooth_union", &[k], n) if n >= 2 => Ok(fold(fields, &|a, b| Sdf::SmoothUnion(k, a, b))),
+        ("smooth_intersect", &[k], n) if n >= 2 => Ok(fold(fields, &|a, b| Sdf::SmoothIntersection(k, a, b))),
+        ("smooth_subtract", &[k], n) if n >= 2 => Ok(fold(fields, &|a, b| Sdf::SmoothSubtraction(k, a, b))),
+        ("translate", &[x, y, z], 1) => Ok(Sdf::Translate(Vector3::new(x, y, z), fields.remove(0))),
+        _ => Err(format_err!("wrong arguments for \"{}\"", name)),
+    }
+}
+
+impl FromStr for Sdf {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Sdf, Error> {
+        build(s.parse()?)
+    }
+}
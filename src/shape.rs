@@ -0,0 +1,102 @@
+//! Shapes which objects are made of, in their own local space.
+//!
+//! A single shape can be shared by any number of objects, each placing it in
+//! the world with its own transform.
+
+use std::f64::NEG_INFINITY;
+use nalg::{Isometry3, Unit};
+use camera::{Ray, Sphere, Castable, Impact};
+use bvh::{Aabb, Bvh};
+use sdf::SdfShape;
+use csg::Solid;
+
+#[derive(Clone, Debug)]
+pub enum Shape {
+    Sphere(Sphere),
+    Sdf(SdfShape),
+    Csg(Solid),
+    Group(Group),
+}
+
+impl Shape {
+    /// a box holding the whole shape, in local space
+    pub fn bounds(&self) -> Aabb {
+        match *self {
+            Shape::Sphere(ref s) => Aabb::ball(s.center, s.radius()),
+            Shape::Sdf(ref s) => s.bounds(),
+            Shape::Csg(ref s) => s.bounds(),
+            Shape::Group(ref g) => g.bounds,
+        }
+    }
+}
+
+impl Castable for Shape {
+    fn cast<T>(&self, ray: Ray, data: T) -> Option<Impact<T>> {
+        match *self {
+            Shape::Sphere(ref s) => s.cast(ray, data),
+            Shape::Sdf(ref s) => s.cast(ray, data),
+            Shape::Csg(ref s) => s.cast(ray, data),
+            Shape::Group(ref g) => g.cast(ray, data),
+        }
+    }
+
+    fn cast_from<T>(&self, ray: Ray, t_min: f64, data: T) -> Option<Impact<T>> {
+        match *self {
+            Shape::Group(ref g) => g.cast_from(ray, t_min, data),
+            _ => self.cast(ray, data).filter(|i| i.t > t_min),
+        }
+    }
+}
+
+/// many shapes moved into place and treated as one, with a hierarchy of its
+/// own so that large groups stay fast to hit
+#[derive(Clone, Debug)]
+pub struct Group {
+    parts: Vec<(Isometry3<f64>, Shape)>,
+    bvh: Bvh,
+    bounds: Aabb,
+}
+
+impl Group {
+    pub fn new(parts: Vec<(Isometry3<f64>, Shape)>) -> Group {
+        let bounds: Vec<_> = parts.iter().map(|&(ref iso, ref s)| s.bounds().transformed(iso)).collect();
+        Group {
+            bvh: Bvh::new(&bounds),
+            bounds: bounds.iter().fold(Aabb::empty(), |a, b| a.union(b)),
+            parts,
+        }
+    }
+
+    pub fn parts(&self) -> &[(Isometry3<f64>, Shape)] {
+        &self.parts
+    }
+}
+
+impl Castable for Group {
+    fn cast<T>(&self, ray: Ray, data: T) -> Option<Impact<T>> {
+        self.cast_from(ray, NEG_INFINITY, data)
+    }
+
+    /// each part skips hits closer than `t_min` by itself, so that a ray
+    /// leaving one part can still hit the others
+    fn cast_from<T>(&self, ray: Ray, t_min: f64, data: T) -> Option<Impact<T>> {
+        // parts only need to report where they were hit, the data is attached at the end
+        self.bvh.cast(ray, |i| {
+            let (ref iso, ref shape) = self.parts[i];
+            shape.cast_from(transform_ray(&iso.inverse(), ray), t_min, ()).map(|hit| Impact {
+                t: hit.t,
+                norm: Unit::new_unchecked(iso * hit.norm.unwrap()),
+                data: (),
+            })
+        }).map(|hit| Impact { t: hit.t, norm: hit.norm, data })
+    }
+}
+
+/// move a ray by a transform
+pub fn transform_ray(iso: &Isometry3<f64>, ray: Ray) -> Ray {
+    Ray {
+        origin: iso * ray.origin,
+        dir: Unit::new_unchecked(iso * ray.dir.unwrap()),
+        time: ray.time,
+    }
+}
@@ -0,0 +1,264 @@
+//! Spectral rendering, where each path carries light of a few wavelengths
+//! instead of red, green and blue.
+//!
+//! Paths still carry a `LinSrgb`, but in spectral mode its three channels
+//! hold the radiance at three wavelengths: a hero wavelength picked at random,
+//! and two more spread evenly across the visible range from it. Colors given
+//! as RGB are upsampled to smooth spectra by fitting a sigmoid of a
+//! quadratic, as in Jakob and Hanika's "A Low-Dimensional Function Space for
+//! Efficient Spectral Upsampling", and the radiance reaching the film is
+//! turned back into RGB through the CIE 1931 color matching functions.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::str::FromStr;
+use nalg::{Vector3, Matrix3};
+use palette::LinSrgb;
+use failure::{Error, format_err};
+use medium::Medium;
+
+/// shortest wavelength rendered, in nanometres
+pub const START: f64 = 380.;
+/// longest wavelength rendered, in nanometres
+pub const END: f64 = 780.;
+
+/// wavelength that a single index of refraction is taken to be for, which
+/// is the sodium D line
+const SODIUM_D: f64 = 589.3;
+
+/// a piecewise Gaussian, wider on one side than the other
+fn lobe(lambda: f64, mean: f64, below: f64, above: f64) -> f64 {
+    let t = (lambda - mean) / if lambda < mean { below } else { above };
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 2° color matching functions, using the multi-lobe fit by Wyman,
+/// Sloan and Shirley
+pub fn cie(lambda: f64) -> Vector3<f64> {
+    Vector3::new(
+        1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8),
+    )
+}
+
+fn xyz_to_rgb(xyz: Vector3<f64>) -> Vector3<f64> {
+    Matrix3::new(
+        3.2404542, -1.5371385, -0.4985314,
+        -0.9692660, 1.8760108, 0.0415560,
+        0.0556434, -0.2040259, 1.0572252,
+    ) * xyz
+}
+
+/// number of steps spectra are integrated over
+const STEPS: usize = 80;
+
+/// linear RGB of a spectrum, before white balancing
+fn raw_rgb(spectrum: &dyn Fn(f64) -> f64) -> Vector3<f64> {
+    let step = (END - START) / STEPS as f64;
+    let xyz = (0..STEPS)
+        .map(|i| START + (i as f64 + 0.5) * step)
+        .fold(Vector3::zeros(), |sum, lambda| sum + cie(lambda) * (spectrum(lambda) * step));
+    xyz_to_rgb(xyz / Y_INTEGRAL.with(|y| *y))
+}
+
+thread_local! {
+    /// area under the luminance matching function, so that a flat spectrum of
+    /// 1 has a luminance of 1
+    static Y_INTEGRAL: f64 = {
+        let step = (END - START) / STEPS as f64;
+        (0..STEPS).map(|i| cie(START + (i as f64 + 0.5) * step).y * step).sum()
+    };
+    /// RGB of a flat spectrum, which is divided out so that it comes out white
+    static WHITE: Vector3<f64> = raw_rgb(&|_| 1.);
+    /// fitted spectra of colors that have been seen before
+    static FITS: RefCell<HashMap<[u32; 3], Vector3<f64>>> = RefCell::new(HashMap::new());
+}
+
+/// linear RGB of a spectrum, white balanced so that a flat spectrum is white
+fn rgb(spectrum: &dyn Fn(f64) -> f64) -> Vector3<f64> {
+    let white = WHITE.with(|w| *w);
+    raw_rgb(spectrum).component_div(&white)
+}
+
+/// value of a fitted spectrum at a wavelength, which is always between 0 and 1
+fn sigmoid(coeffs: &Vector3<f64>, lambda: f64) -> f64 {
+    let t = (lambda - START) / (END - START);
+    let x = coeffs.x * t * t + coeffs.y * t + coeffs.z;
+    0.5 + x / (2. * (1. + x * x).sqrt())
+}
+
+/// find the smooth spectrum closest to a color with channels between 0 and 1
+fn fit(target: Vector3<f64>) -> Vector3<f64> {
+    const STAGES: usize = 8;
+    const ITERATIONS: usize = 8;
+    const H: f64 = 1e-4;
+
+    // saturated colors need big coefficients, so work out towards them from grey
+    let grey = Vector3::repeat(0.5);
+    let mut coeffs = Vector3::zeros();
+    for stage in 1..=STAGES {
+        let goal = grey + (target - grey) * (stage as f64 / STAGES as f64);
+        for _ in 0..ITERATIONS {
+            let residual = rgb(&|l| sigmoid(&coeffs, l)) - goal;
+            if residual.norm() < 1e-6 { break }
+
+            let mut jacobian = Matrix3::zeros();
+            for j in 0..3 {
+                let mut moved = coeffs;
+                moved[j] += H;
+                jacobian.set_column(j, &((rgb(&|l| sigmoid(&moved, l)) - goal - residual) / H));
+            }
+            match jacobian.try_inverse() {
+                Some(inverse) => coeffs -= inverse * residual,
+                None => break,
+            }
+        }
+    }
+    coeffs
+}
+
+/// fitted spectrum of a color with channels between 0 and 1, remembered so
+/// that it is only fitted once
+fn fitted(c: LinSrgb) -> Vector3<f64> {
+    // the ends of the range can only be reached by infinitely steep spectra
+    let clamp = |v: f32| v.max(1e-3).min(1. - 1e-3);
+    let c = LinSrgb::new(clamp(c.red), clamp(c.green), clamp(c.blue));
+    let key = [c.red.to_bits(), c.green.to_bits(), c.blue.to_bits()];
+    FITS.with(|fits| {
+        *fits.borrow_mut().entry(key)
+            .or_insert_with(|| fit(Vector3::new(c.red as f64, c.green as f64, c.blue as f64)))
+    })
+}
+
+/// what the three channels of the colors carried by a path mean
+#[derive(Copy, Clone, Debug)]
+pub enum Channels {
+    /// red, green and blue
+    Rgb,
+    /// radiance at each of these wavelengths, in nanometres, where the first
+    /// is the hero wavelength
+    Spectral([f64; 3]),
+}
+
+impl Channels {
+    /// pick a hero wavelength, and two more evenly spaced after it
+    pub fn sample(u: f64) -> Channels {
+        let range = END - START;
+        let at = |i: usize| START + (u * range + i as f64 * range / 3.) % range;
+        Channels::Spectral([at(0), at(1), at(2)])
+    }
+
+    fn each(&self, wavelengths: &[f64; 3], f: impl Fn(f64) -> f64) -> LinSrgb {
+        LinSrgb::new(f(wavelengths[0]) as f32, f(wavelengths[1]) as f32, f(wavelengths[2]) as f32)
+    }
+
+    /// a color which is never more than 1, like the fraction of light
+    /// reflected by a surface, in these channels
+    pub fn reflectance(&self, c: LinSrgb) -> LinSrgb {
+        match *self {
+            Channels::Rgb => c,
+            Channels::Spectral(ref wavelengths) => {
+                let coeffs = fitted(c);
+                self.each(wavelengths, |l| sigmoid(&coeffs, l))
+            },
+        }
+    }
+
+    /// a color which can be any brightness, like the light given off by a
+    /// surface, in these channels
+    pub fn radiance(&self, c: LinSrgb) -> LinSrgb {
+        match *self {
+            Channels::Rgb => c,
+            Channels::Spectral(ref wavelengths) => {
+                // fit the color scaled down to a brightness of 1/2, and scale the spectrum back up
+                let scale = 2. * c.red.max(c.green).max(c.blue);
+                if scale <= 0. { return LinSrgb::new(0., 0., 0.) }
+                let coeffs = fitted(c / scale);
+                self.each(wavelengths, |l| sigmoid(&coeffs, l) * scale as f64)
+            },
+        }
+    }
+
+    /// a medium with its coefficients in these channels
+    pub fn medium(&self, m: &Medium) -> Medium {
+        Medium {
+            absorption: self.radiance(m.absorption),
+            scattering: self.radiance(m.scattering),
+            ..m.clone()
+        }
+    }
+
+    /// linear RGB of light carried in these channels
+    pub fn to_rgb(&self, c: LinSrgb) -> LinSrgb {
+        match *self {
+            Channels::Rgb => c,
+            Channels::Spectral(ref wavelengths) => {
+                // each wavelength was picked uniformly, and stands for a third of the range
+                let weight = (END - START) / 3. / Y_INTEGRAL.with(|y| *y);
+                let values = [c.red, c.green, c.blue];
+                let xyz = wavelengths.iter().zip(values.iter())
+                    .fold(Vector3::zeros(), |sum, (&l, &v)| sum + cie(l) * (v as f64 * weight));
+                let rgb = xyz_to_rgb(xyz).component_div(&WHITE.with(|w| *w));
+                LinSrgb::new(rgb.x as f32, rgb.y as f32, rgb.z as f32)
+            },
+        }
+    }
+}
+
+/// how the index of refraction of a material changes with wavelength
+#[derive(Copy, Clone, Debug)]
+pub enum Ior {
+    /// the same for every wavelength
+    Constant(f64),
+    /// Cauchy's equation, `a + b / λ²`, with λ in micrometres
+    Cauchy { a: f64, b: f64 },
+    /// the Sellmeier equation, `n² = 1 + Σ b λ² / (λ² - c)`, with λ in micrometres
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Ior {
+    /// index of refraction at a wavelength, in nanometres
+    pub fn at(&self, lambda: f64) -> f64 {
+        let l2 = (lambda / 1000.) * (lambda / 1000.);
+        match *self {
+            Ior::Constant(n) => n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => (1. + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()).sqrt(),
+        }
+    }
+
+    /// index of refraction for light carried in some channels, and the filter
+    /// to apply to it
+    ///
+    /// Light of different wavelengths is bent by different amounts, so only
+    /// the hero wavelength can carry on, making up for the others.
+    pub fn in_channels(&self, channels: Channels) -> (f64, LinSrgb) {
+        match (*self, channels) {
+            (Ior::Constant(n), _) => (n, LinSrgb::new(1., 1., 1.)),
+            (_, Channels::Rgb) => (self.at(SODIUM_D), LinSrgb::new(1., 1., 1.)),
+            (_, Channels::Spectral(wavelengths)) => (self.at(wavelengths[0]), LinSrgb::new(3., 0., 0.)),
+        }
+    }
+}
+
+impl FromStr for Ior {
+    type Err = Error;
+
+    /// parse a plain number, `cauchy:A,B` or `sellmeier:B1,B2,B3,C1,C2,C3`
+    fn from_str(s: &str) -> Result<Ior, Error> {
+        let (kind, rest) = match s.find(':') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => return Ok(Ior::Constant(s.trim().parse()?)),
+        };
+        let v = rest.split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()?;
+        match (kind, &v[..]) {
+            ("cauchy", &[a, b]) => Ok(Ior::Cauchy { a, b }),
+            ("sellmeier", &[b1, b2, b3, c1, c2, c3]) => Ok(Ior::Sellmeier { b: [b1, b2, b3], c: [c1, c2, c3] }),
+            _ => Err(format_err!("expected a number, \"cauchy:A,B\" or \"sellmeier:B1,B2,B3,C1,C2,C3\"")),
+        }
+    }
+}
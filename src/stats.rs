@@ -5,6 +5,8 @@ pub trait BackPath {
 
     fn decide(&mut self, prob: f32);
     fn decide_not(&mut self, prob: f32) { self.decide(1. - prob) }
+    /// record that the path was extended by another bounce
+    fn bounce(&mut self) {}
     fn source(self, color: <Self::Forward as ForPath>::Color) -> Self::Forward;
 }
 
@@ -18,11 +20,12 @@ pub trait ForPath {
 
 pub struct MulBackPath {
     prob: f32,
+    bounces: usize,
 }
 
 impl MulBackPath {
     pub fn new() -> MulBackPath {
-        MulBackPath { prob: 1. }
+        MulBackPath { prob: 1., bounces: 0 }
     }
 }
 
@@ -33,15 +36,28 @@ impl BackPath for MulBackPath {
         self.prob *= prob;
     }
 
+    fn bounce(&mut self) {
+        self.bounces += 1;
+    }
+
     fn source(self, color: LinSrgb) -> MulForPath {
         MulForPath {
             lum: color / self.prob,
+            bounces: self.bounces,
         }
     }
 }
 
 pub struct MulForPath {
     lum: LinSrgb,
+    bounces: usize,
+}
+
+impl MulForPath {
+    /// number of times the light bounced before reaching the camera
+    pub fn bounces(&self) -> usize {
+        self.bounces
+    }
 }
 
 impl ForPath for MulForPath {
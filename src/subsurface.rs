@@ -0,0 +1,100 @@
+//! Materials which light sinks into and scatters around inside of before
+//! coming back out, like skin, wax and marble.
+//!
+//! Light crossing the surface is reflected or refracted as at the boundary
+//! of a smooth dielectric, and inside it takes a random walk through a
+//! homogeneous medium until it leaves again or is absorbed.
+
+use std::str::FromStr;
+use nalg::{Vector3, Unit};
+use palette::LinSrgb;
+use failure::{Error, format_err};
+use medium::{Medium, Density};
+use spectral::{Channels, Ior};
+
+/// a color given as `r,g,b`, such as a distance for each channel
+#[derive(Copy, Clone, Debug)]
+pub struct Rgb(pub LinSrgb);
+
+impl FromStr for Rgb {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Rgb, Error> {
+        let v = s.split(',')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()?;
+        match v[..] {
+            [x] => Ok(Rgb(LinSrgb::new(x, x, x))),
+            [r, g, b] => Ok(Rgb(LinSrgb::new(r, g, b))),
+            _ => Err(format_err!("expected one value or three, like \"r,g,b\"")),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Subsurface {
+    /// average distance light travels inside before it next scatters or is
+    /// absorbed, for each channel
+    pub mean_free_path: LinSrgb,
+    /// fraction of light scattered rather than absorbed each time
+    pub albedo: LinSrgb,
+    /// index of refraction of the surface
+    pub ior: Ior,
+    /// Henyey-Greenstein asymmetry of each scattering
+    pub g: f64,
+}
+
+impl Subsurface {
+    /// whether light passes straight through without ever scattering, as
+    /// through glass
+    pub fn clear(&self) -> bool {
+        let c = self.mean_free_path;
+        c.red.is_infinite() && c.green.is_infinite() && c.blue.is_infinite()
+    }
+
+    /// the medium that light walks through inside the object, for light
+    /// carried in some channels
+    pub fn medium(&self, channels: Channels) -> Medium {
+        // channels with a longer path are stopped less often, and an
+        // infinite path never is
+        let channel = |mfp: f32| if mfp > 0. { 1. / mfp } else { 0. };
+        let extinction = channels.radiance(LinSrgb::new(
+            channel(self.mean_free_path.red),
+            channel(self.mean_free_path.green),
+            channel(self.mean_free_path.blue),
+        ));
+        let scattering = extinction * channels.reflectance(self.albedo);
+        Medium {
+            absorption: extinction - scattering,
+            scattering,
+            g: self.g,
+            density: Density::Homogeneous,
+        }
+    }
+}
+
+/// fraction of unpolarized light reflected at a smooth boundary, where
+/// `cos_i` is the cosine of the angle to the normal, and `eta` is the index of
+/// refraction of the side the light arrives from, over that of the other side
+pub fn fresnel(cos_i: f64, eta: f64) -> f64 {
+    let sin2_t = eta * eta * (1. - cos_i * cos_i);
+    if sin2_t >= 1. {
+        // total internal reflection
+        return 1.
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (s * s + p * p) / 2.
+}
+
+/// bend a direction through a boundary, given a normal facing against it
+///
+/// Returns `None` if all the light is reflected instead.
+pub fn refract(dir: Unit<Vector3<f64>>, norm: Unit<Vector3<f64>>, eta: f64) -> Option<Unit<Vector3<f64>>> {
+    let cos_i = -dir.dot(&norm);
+    let sin2_t = eta * eta * (1. - cos_i * cos_i);
+    if sin2_t >= 1. { return None }
+    let cos_t = (1. - sin2_t).sqrt();
+    Some(Unit::new_normalize(dir.unwrap() * eta + norm.unwrap() * (eta * cos_i - cos_t)))
+}